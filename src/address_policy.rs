@@ -0,0 +1,99 @@
+use std::{net::IpAddr, sync::LazyLock};
+
+use ipnet::IpNet;
+
+/// Ranges that can't be the public address of a host, and what their addresses are.
+const RESERVED_RANGES: [(&str, &str); 29] = [
+    ("0.0.0.0/8", "a \"this network\" address"),
+    ("10.0.0.0/8", "a private address"),
+    ("100.64.0.0/10", "a CG-NAT address"),
+    ("127.0.0.0/8", "a loopback address"),
+    ("169.254.0.0/16", "a link-local address"),
+    ("172.16.0.0/12", "a private address"),
+    ("192.0.0.0/24", "an IETF protocol assignment"),
+    ("192.0.2.0/24", "a documentation address"),
+    ("192.88.99.0/24", "a 6to4 relay address"),
+    ("192.168.0.0/16", "a private address"),
+    ("198.18.0.0/15", "a benchmarking address"),
+    ("198.51.100.0/24", "a documentation address"),
+    ("203.0.113.0/24", "a documentation address"),
+    ("224.0.0.0/4", "a multicast address"),
+    ("240.0.0.0/4", "a reserved address"),
+    ("::/128", "the unspecified address"),
+    ("::1/128", "a loopback address"),
+    ("::ffff:0:0/96", "an IPv4-mapped address"),
+    ("64:ff9b::/96", "a NAT64 address"),
+    ("100::/64", "a discard address"),
+    ("2001::/32", "a Teredo address"),
+    ("2001:2::/48", "a benchmarking address"),
+    ("2001:db8::/32", "a documentation address"),
+    ("2002::/16", "a 6to4 address"),
+    ("3fff::/20", "a documentation address"),
+    ("fc00::/7", "a unique local address"),
+    ("fe80::/10", "a link-local address"),
+    ("fec0::/10", "a site-local address"),
+    ("ff00::/8", "a multicast address"),
+];
+
+/// [`RESERVED_RANGES`] parsed once, since every WAN IP is checked against them.
+static RESERVED_NETS: LazyLock<Vec<(IpNet, &str)>> = LazyLock::new(|| {
+    RESERVED_RANGES
+        .iter()
+        .map(|(range, reason)| (range.parse().expect("Reserved ranges are valid"), *reason))
+        .collect()
+});
+
+const DENIED_REASON: &str = "in a denied range";
+
+/// Decides which WAN IPs may be written to records, so a misbehaving source can't publish
+/// an address like 192.168.1.1. Reserved ranges are always rejected unless they're allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressPolicy {
+    /// Ranges that are rejected in addition to the reserved ranges, even if they're allowed.
+    pub denied: Vec<IpNet>,
+    /// Reserved ranges that are accepted anyway, like a private range for records on an
+    /// internal DNS server.
+    pub allowed: Vec<IpNet>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{ip} is {reason} ({range})")]
+pub struct RejectedAddress {
+    pub ip: IpAddr,
+    /// The range that the address is in.
+    pub range: IpNet,
+    pub reason: &'static str,
+}
+
+impl RejectedAddress {
+    /// Returns true if the address is in a reserved range rather than in a denied one, so it
+    /// can't become public by asking again.
+    pub fn is_reserved(&self) -> bool {
+        self.reason != DENIED_REASON
+    }
+}
+
+impl AddressPolicy {
+    pub fn check(&self, ip: IpAddr) -> Result<(), RejectedAddress> {
+        if let Some(range) = self.denied.iter().find(|range| range.contains(&ip)) {
+            return Err(RejectedAddress {
+                ip,
+                range: *range,
+                reason: DENIED_REASON,
+            });
+        }
+
+        if self.allowed.iter().any(|range| range.contains(&ip)) {
+            return Ok(());
+        }
+
+        match RESERVED_NETS.iter().find(|(range, _)| range.contains(&ip)) {
+            Some((range, reason)) => Err(RejectedAddress {
+                ip,
+                range: *range,
+                reason,
+            }),
+            None => Ok(()),
+        }
+    }
+}
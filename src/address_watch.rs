@@ -0,0 +1,150 @@
+use std::{collections::BTreeSet, net::IpAddr, time::Duration};
+
+#[cfg(target_os = "linux")]
+use netlink_sys::{protocols::NETLINK_ROUTE, AsyncSocket, AsyncSocketExt, SocketAddr, TokioSocket};
+
+/// How often the addresses are compared when there are no netlink notifications.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to wait after a notification for more changes, like an interface that gets both
+/// an IPv4 and an IPv6 address when a PPPoE session comes up.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+// Multicast groups from linux/rtnetlink.h
+#[cfg(target_os = "linux")]
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+#[cfg(target_os = "linux")]
+const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+/// Waits for the addresses of the local network interfaces to change, so the records can be
+/// updated right away instead of at the next update interval.
+pub struct AddressWatcher {
+    addresses: BTreeSet<(String, IpAddr)>,
+    events: Events,
+}
+
+/// What wakes the watcher up to compare the addresses.
+enum Events {
+    /// Address notifications (RTM_NEWADDR and RTM_DELADDR) of the kernel.
+    #[cfg(target_os = "linux")]
+    Netlink(TokioSocket),
+    Poll(tokio::time::Interval),
+}
+
+impl AddressWatcher {
+    /// Subscribes to the address notifications of the kernel, or compares the addresses
+    /// every few seconds where those aren't available.
+    pub fn new() -> Self {
+        AddressWatcher {
+            addresses: local_addresses(),
+            events: Events::new(),
+        }
+    }
+
+    /// Returns once the addresses differ from the last time they were read.
+    /// Notifications that don't change the addresses, like renewed IPv6 lifetimes, are ignored.
+    pub async fn changed(&mut self) {
+        loop {
+            self.events.next().await;
+            tokio::time::sleep(SETTLE_DELAY).await;
+
+            let addresses = local_addresses();
+
+            if addresses == self.addresses {
+                continue;
+            }
+
+            for (interface, ip) in addresses.difference(&self.addresses) {
+                log::info!("Address {ip} was added to {interface}");
+            }
+
+            for (interface, ip) in self.addresses.difference(&addresses) {
+                log::info!("Address {ip} was removed from {interface}");
+            }
+
+            self.addresses = addresses;
+            return;
+        }
+    }
+}
+
+impl Default for AddressWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Events {
+    #[cfg(target_os = "linux")]
+    fn new() -> Self {
+        match netlink_socket() {
+            Ok(socket) => Events::Netlink(socket),
+            Err(err) => {
+                log::warn!("Failed to subscribe to address changes ({err}), polling instead");
+                Events::poll()
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new() -> Self {
+        Events::poll()
+    }
+
+    fn poll() -> Self {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        Events::Poll(interval)
+    }
+
+    async fn next(&mut self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Events::Netlink(socket) => {
+                // The notification is only a hint, the addresses are read afterwards anyway
+                if let Err(err) = socket.recv_from_full().await {
+                    log::warn!("Failed to receive address changes ({err}), polling instead");
+                    *self = Events::poll();
+                }
+            }
+            Events::Poll(interval) => {
+                interval.tick().await;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn netlink_socket() -> std::io::Result<TokioSocket> {
+    let mut socket = TokioSocket::new(NETLINK_ROUTE)?;
+    socket
+        .socket_mut()
+        .bind(&SocketAddr::new(0, RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR))?;
+
+    Ok(socket)
+}
+
+/// Returns the addresses of all interfaces, except loopback and link-local addresses
+/// which don't affect the WAN IP.
+fn local_addresses() -> BTreeSet<(String, IpAddr)> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            log::warn!("Failed to read the addresses of the network interfaces: {err}");
+            return BTreeSet::new();
+        }
+    };
+
+    interfaces
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .map(|interface| {
+            let ip = interface.ip();
+            (interface.name, ip)
+        })
+        .filter(|(_, ip)| match ip {
+            IpAddr::V4(ip) => !ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 != 0xfe80,
+        })
+        .collect()
+}
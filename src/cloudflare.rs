@@ -0,0 +1,392 @@
+use async_trait::async_trait;
+use reqwest::{header::HeaderMap, Client, ClientBuilder, Method, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::provider::{
+    absolute_record_name, relative_record_name, DnsProvider, DnsRecord, ProviderError, Zone,
+};
+
+pub const DEFAULT_API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+/// Maximum page size allowed by the zones endpoint.
+const ZONES_PER_PAGE: u32 = 50;
+/// Maximum page size allowed by the dns_records endpoint.
+const RECORDS_PER_PAGE: u32 = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct CloudflareZone {
+    /// Identifier of the zone.
+    pub id: String,
+    /// The domain name, for example example.com.
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloudflareRecord {
+    /// Identifier of the record.
+    pub id: String,
+    /// Record type. For example: A, AAAA, CNAME, ...
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The fully qualified domain name of the record, for example home.example.com.
+    pub name: String,
+    /// The value of the record, for example the IPv4 address of an A record.
+    pub content: String,
+    /// Time to live in seconds. A value of 1 means the TTL is managed automatically by Cloudflare.
+    pub ttl: u32,
+    /// Whether the record is receiving the performance and security benefits of Cloudflare.
+    pub proxied: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMessage {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultInfo {
+    page: u32,
+    total_pages: u32,
+}
+
+/// Every Cloudflare API response is wrapped in this envelope.
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiMessage>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct PatchRecordRequestData<'a> {
+    #[serde(rename = "type")]
+    ty: &'a str,
+    content: &'a str,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRecordRequestData<'a> {
+    #[serde(rename = "type")]
+    ty: &'a str,
+    name: &'a str,
+    content: &'a str,
+    ttl: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("RateLimited: {0}")]
+    RateLimited(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("NotFound: {0}")]
+    NotFound(String),
+    #[error("ApiError: {0}")]
+    ApiError(String),
+    #[error("Unexpected status code: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error("ReqwestError: {0}")]
+    ReqwestError(reqwest::Error),
+}
+
+impl From<reqwest::Error> for QueryError {
+    fn from(err: reqwest::Error) -> Self {
+        QueryError::ReqwestError(err)
+    }
+}
+
+impl From<QueryError> for ProviderError {
+    fn from(err: QueryError) -> Self {
+        match err {
+            QueryError::Unauthorized(message) => ProviderError::Unauthorized(message),
+            QueryError::RateLimited(message) => ProviderError::RateLimited(message),
+            QueryError::NotFound(message) => ProviderError::NotFound(message),
+            err => ProviderError::Other(err.into()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NewClientError {
+    #[error("ReqwestError: {0}")]
+    ReqwestError(reqwest::Error),
+    #[error("Invalid api token format")]
+    InvalidApiToken,
+}
+
+impl From<reqwest::Error> for NewClientError {
+    fn from(err: reqwest::Error) -> Self {
+        NewClientError::ReqwestError(err)
+    }
+}
+
+pub struct Cloudflare {
+    client: Client,
+    api_base: String,
+}
+
+impl Cloudflare {
+    /// Creates a new client that authenticates with an API token.
+    /// `api_base` is normally [`DEFAULT_API_BASE`], but can be pointed at a mock server.
+    pub fn new(api_token: String, api_base: &str) -> Result<Self, NewClientError> {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            "Authorization",
+            format!("Bearer {api_token}")
+                .parse()
+                .map_err(|_| NewClientError::InvalidApiToken)?,
+        );
+
+        Ok(Cloudflare {
+            client: ClientBuilder::new()
+                .default_headers(default_headers)
+                .build()
+                .map_err(NewClientError::ReqwestError)?,
+            api_base: api_base.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub async fn list_zones(&self) -> Result<Vec<CloudflareZone>, QueryError> {
+        self.get_all_pages(&format!("/zones?per_page={ZONES_PER_PAGE}"))
+            .await
+    }
+
+    pub async fn list_dns_records(
+        &self,
+        zone_id: &str,
+    ) -> Result<Vec<CloudflareRecord>, QueryError> {
+        self.get_all_pages(&format!(
+            "/zones/{zone_id}/dns_records?per_page={RECORDS_PER_PAGE}"
+        ))
+        .await
+    }
+
+    /// Changes the content of a record. The `proxied` flag is sent back unchanged
+    /// so that proxied records are not accidentally exposed.
+    pub async fn patch_dns_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        ty: &str,
+        content: &str,
+        ttl: u32,
+        proxied: Option<bool>,
+    ) -> Result<CloudflareRecord, QueryError> {
+        self.make_request_with_data(
+            &format!("/zones/{zone_id}/dns_records/{record_id}"),
+            Method::PATCH,
+            &PatchRecordRequestData {
+                ty,
+                content,
+                ttl,
+                proxied,
+            },
+        )
+        .await
+    }
+
+    pub async fn create_dns_record(
+        &self,
+        zone_id: &str,
+        name: &str,
+        ty: &str,
+        content: &str,
+        ttl: u32,
+    ) -> Result<CloudflareRecord, QueryError> {
+        self.make_request_with_data(
+            &format!("/zones/{zone_id}/dns_records"),
+            Method::POST,
+            &CreateRecordRequestData {
+                ty,
+                name,
+                content,
+                ttl,
+            },
+        )
+        .await
+    }
+
+    pub async fn delete_dns_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<(), QueryError> {
+        let path = format!("{}/zones/{zone_id}/dns_records/{record_id}", self.api_base);
+        let response = self.client.request(Method::DELETE, &path).send().await?;
+
+        Self::handle_response::<serde_json::Value>(response)
+            .await
+            .map(|_| ())
+    }
+
+    async fn get_all_pages<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, QueryError> {
+        let mut results = vec![];
+        let mut page = 1;
+
+        loop {
+            let url = format!("{}{}&page={page}", self.api_base, path);
+            let response = self.client.request(Method::GET, &url).send().await?;
+            let (result, result_info) = Self::handle_paged_response::<Vec<T>>(response).await?;
+
+            results.extend(result);
+
+            match result_info {
+                Some(info) if info.page < info.total_pages => page = info.page + 1,
+                _ => break,
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn make_request_with_data<RequestData: Serialize, ResponseData: DeserializeOwned>(
+        &self,
+        path: &str,
+        method: Method,
+        data: &RequestData,
+    ) -> Result<ResponseData, QueryError> {
+        let path = format!("{}{}", self.api_base, path);
+        let response = self.client.request(method, &path).json(data).send().await?;
+
+        Self::handle_response(response).await
+    }
+
+    async fn handle_response<ResponseData: DeserializeOwned>(
+        response: Response,
+    ) -> Result<ResponseData, QueryError> {
+        Ok(Self::handle_paged_response(response).await?.0)
+    }
+
+    async fn handle_paged_response<ResponseData: DeserializeOwned>(
+        response: Response,
+    ) -> Result<(ResponseData, Option<ResultInfo>), QueryError> {
+        let status_code = response.status();
+
+        // Cloudflare includes the error details in the response body for all status codes,
+        // but some errors (like 5xx from the edge) may not be json.
+        let body = match response.json::<ApiResponse<ResponseData>>().await {
+            Ok(body) => body,
+            Err(err) if status_code.is_success() => return Err(err.into()),
+            Err(_) => return Err(QueryError::UnexpectedStatus(status_code)),
+        };
+
+        let message = body
+            .errors
+            .iter()
+            .map(|err| format!("{} ({})", err.message, err.code))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match status_code {
+            _ if status_code.is_success() && body.success => match body.result {
+                Some(result) => Ok((result, body.result_info)),
+                None => Err(QueryError::ApiError(
+                    "Response did not contain a result".to_string(),
+                )),
+            },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(QueryError::Unauthorized(message))
+            }
+            StatusCode::NOT_FOUND => Err(QueryError::NotFound(message)),
+            StatusCode::TOO_MANY_REQUESTS => Err(QueryError::RateLimited(message)),
+            _ if !message.is_empty() => Err(QueryError::ApiError(message)),
+            other => Err(QueryError::UnexpectedStatus(other)),
+        }
+    }
+}
+
+impl From<CloudflareZone> for Zone {
+    fn from(zone: CloudflareZone) -> Self {
+        Zone {
+            id: zone.id,
+            name: zone.name,
+        }
+    }
+}
+
+impl CloudflareRecord {
+    fn into_dns_record(self, zone: &Zone) -> DnsRecord {
+        DnsRecord {
+            id: self.id,
+            ty: self.ty,
+            name: relative_record_name(&self.name, &zone.name),
+            data: self.content,
+            ttl: self.ttl,
+            proxied: self.proxied,
+        }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    fn name(&self) -> &'static str {
+        "Cloudflare"
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        Ok(Cloudflare::list_zones(self)
+            .await?
+            .into_iter()
+            .map(Zone::from)
+            .collect())
+    }
+
+    async fn list_records(
+        &self,
+        zone: &Zone,
+        _names: &[&str],
+    ) -> Result<Vec<DnsRecord>, ProviderError> {
+        Ok(self
+            .list_dns_records(&zone.id)
+            .await?
+            .into_iter()
+            .map(|record| record.into_dns_record(zone))
+            .collect())
+    }
+
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &DnsRecord,
+        new_value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        Ok(self
+            .patch_dns_record(
+                &zone.id,
+                &record.id,
+                &record.ty,
+                new_value,
+                ttl,
+                record.proxied,
+            )
+            .await?
+            .into_dns_record(zone))
+    }
+
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        name: &str,
+        ty: &str,
+        value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        let name = absolute_record_name(name, &zone.name);
+
+        Ok(self
+            .create_dns_record(&zone.id, &name, ty, value, ttl)
+            .await?
+            .into_dns_record(zone))
+    }
+
+    async fn delete_record(&self, zone: &Zone, record: &DnsRecord) -> Result<(), ProviderError> {
+        Ok(self.delete_dns_record(&zone.id, &record.id).await?)
+    }
+}
@@ -0,0 +1,554 @@
+use std::{
+    collections::BTreeMap,
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{parser::ValueSource, ArgMatches};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use crate::{
+    address_policy::AddressPolicy,
+    cloudflare, digitalocean,
+    domain_arg::DomainArg,
+    provider::{NewProviderError, ProviderConfig},
+    rfc2136::{self, Rfc2136Config, TsigAlgorithmArg, TsigKey},
+    wan_ip_query::{HttpEndpoint, IpFamily, IpSourceSpec},
+    AppArgs,
+};
+
+/// Name of the provider that is configured with command line arguments.
+const CLI_PROVIDER_NAME: &str = "cli";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Failed to parse config file {0}: {1}")]
+    Parse(String, toml::de::Error),
+    #[error("Provider \"{0}\": {1}")]
+    InvalidProvider(String, NewProviderError),
+    #[error("Record entry {0} uses undefined provider \"{1}\"")]
+    UnknownProvider(usize, String),
+    #[error("Record entry {0} has an invalid domain \"{1}\": {2}")]
+    InvalidDomain(usize, String, String),
+    #[error("Record entry {0} has an invalid record family \"{1}\", expected A or AAAA")]
+    InvalidFamily(usize, String),
+    #[error(
+        "Record entry {0} has no record families, remove `families` to update both A and AAAA"
+    )]
+    EmptyFamilies(usize),
+    #[error("Invalid IPv6 prefix length {0}, expected 0 to 128")]
+    InvalidIpv6PrefixLength(u8),
+    #[error("An IP quorum of {0} can't be reached with {1} WAN IP sources")]
+    UnreachableQuorum(usize, usize),
+    #[error("The minimum TTL {0} is larger than the maximum TTL {1}")]
+    InvalidTtlRange(u32, u32),
+    #[error(
+        "The provider name \"{0}\" is reserved for the provider configured on the command line"
+    )]
+    ReservedProviderName(String),
+    #[error("No domains were specified, use --domain or add [[records]] to the config file")]
+    NoDomains,
+}
+
+/// The contents of the toml config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub update_interval: Option<i64>,
+    pub watch_addresses: Option<bool>,
+    pub apply: Option<bool>,
+    pub skip_warning: Option<bool>,
+    pub create_missing: Option<bool>,
+    pub create_ttl: Option<u32>,
+    pub ttl: Option<u32>,
+    pub min_ttl: Option<u32>,
+    pub max_ttl: Option<u32>,
+    pub ipv6_prefix_length: Option<u8>,
+    pub state_file: Option<PathBuf>,
+    pub max_staleness: Option<i64>,
+    /// The default WAN IP sources of the record entries.
+    pub ip_sources: Option<Vec<IpSourceSpec>>,
+    pub ip_quorum: Option<NonZeroUsize>,
+    pub ip_confirmations: Option<NonZeroU32>,
+    pub ip_confirm_delay: Option<u64>,
+    pub ip_confirm_duration: Option<u64>,
+    pub denied_ranges: Option<Vec<IpNet>>,
+    pub allowed_ranges: Option<Vec<IpNet>>,
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderSection>,
+    #[serde(default)]
+    pub records: Vec<RecordSection>,
+}
+
+/// A `[providers.<name>]` table.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ProviderSection {
+    DigitalOcean {
+        api_key: String,
+        api_base: Option<String>,
+    },
+    Cloudflare {
+        api_token: String,
+        api_base: Option<String>,
+    },
+    Rfc2136 {
+        server: String,
+        zones: Vec<String>,
+        #[serde(default)]
+        tcp: bool,
+        tsig_key_name: Option<String>,
+        tsig_algorithm: Option<TsigAlgorithmArg>,
+        tsig_secret: Option<String>,
+    },
+}
+
+/// A `[[records]]` entry.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordSection {
+    /// Name of the provider in `[providers]`.
+    pub provider: String,
+    pub domains: Vec<String>,
+    /// The record types to update for domains without a `:A`/`:AAAA` suffix, defaults to both.
+    pub families: Option<Vec<String>>,
+    /// TTL of the records of this entry, both existing and created ones.
+    pub ttl: Option<u32>,
+    pub min_ttl: Option<u32>,
+    pub max_ttl: Option<u32>,
+    pub create_missing: Option<bool>,
+    /// How many bits of the WAN IPv6 address are kept for domains with an interface identifier.
+    pub ipv6_prefix_length: Option<u8>,
+    /// WAN IP endpoints to use instead of the ones in api_urls.txt, in the same format.
+    /// Shorthand for an `http:<endpoint>` source with all endpoints.
+    pub ip_urls: Option<Vec<HttpEndpoint>>,
+    /// The WAN IP sources of this entry, tried in order.
+    pub ip_sources: Option<Vec<IpSourceSpec>>,
+}
+
+/// The resolved settings of the updater, after merging the config file and command line.
+#[derive(Debug)]
+pub struct Settings {
+    pub update_interval: Option<i64>,
+    /// Whether to update right away when the local addresses change.
+    pub watch_addresses: bool,
+    pub apply: bool,
+    pub skip_warning: bool,
+    pub state_file: PathBuf,
+    /// How long (in minutes) the state of a record is trusted.
+    pub max_staleness: i64,
+    /// How many WAN IP sources have to agree on the IP, `None` to use the first one that answers.
+    pub ip_quorum: Option<NonZeroUsize>,
+    /// How many consecutive queries have to report a changed WAN IP before it's used.
+    pub ip_confirmations: NonZeroU32,
+    /// How long to wait before querying a changed WAN IP again.
+    pub ip_confirm_delay: Duration,
+    /// How long a changed WAN IP has to be reported for before it's used.
+    pub ip_confirm_duration: Duration,
+    /// Which WAN IPs may be written to records.
+    pub address_policy: AddressPolicy,
+    pub providers: BTreeMap<String, ProviderConfig>,
+    pub entries: Vec<RecordEntry>,
+}
+
+/// A group of domains that share a provider and update policy.
+#[derive(Debug)]
+pub struct RecordEntry {
+    /// Name of the provider in [`Settings::providers`].
+    pub provider: String,
+    pub domains: Vec<DomainArg>,
+    /// The TTL of missing records that should be created, `None` if they shouldn't be created.
+    pub create_ttl: Option<u32>,
+    /// The TTL that existing records should have.
+    pub ttl: TtlPolicy,
+    /// Where to get the WAN IP from, tried in order until one of them succeeds.
+    pub ip_sources: Vec<IpSourceSpec>,
+    /// How many bits of the WAN IPv6 address are kept for domains with an interface identifier.
+    pub ipv6_prefix_length: u8,
+}
+
+/// The TTL that the records of an entry should have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TtlPolicy {
+    /// Records with another TTL are changed to this one.
+    pub ttl: Option<u32>,
+    /// Records with a lower TTL are raised to this one.
+    pub min_ttl: Option<u32>,
+    /// Records with a higher TTL are lowered to this one.
+    pub max_ttl: Option<u32>,
+}
+
+impl TtlPolicy {
+    /// Returns the TTL that a record with the TTL `current` should have.
+    pub fn desired(&self, current: u32) -> u32 {
+        let ttl = self.ttl.unwrap_or(current);
+        let ttl = self.min_ttl.map_or(ttl, |min_ttl| ttl.max(min_ttl));
+
+        self.max_ttl.map_or(ttl, |max_ttl| ttl.min(max_ttl))
+    }
+
+    /// Returns true if the TTL of records is left as it is.
+    pub fn is_unmanaged(&self) -> bool {
+        *self == TtlPolicy::default()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        match (self.min_ttl, self.max_ttl) {
+            (Some(min_ttl), Some(max_ttl)) if min_ttl > max_ttl => {
+                Err(ConfigError::InvalidTtlRange(min_ttl, max_ttl))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Settings {
+    /// Returns all domains of all entries.
+    pub fn domains(&self) -> impl Iterator<Item = &DomainArg> {
+        self.entries.iter().flat_map(|entry| entry.domains.iter())
+    }
+}
+
+pub fn load_config_file(path: &Path) -> Result<ConfigFile, ConfigError> {
+    let display_path = path.display().to_string();
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| ConfigError::Io(display_path.clone(), err))?;
+
+    toml::from_str(&contents).map_err(|err| ConfigError::Parse(display_path, err))
+}
+
+/// Returns the value of a command line argument if it was explicitly set,
+/// either on the command line or with an environment variable.
+fn explicit_arg<T: Clone>(matches: &ArgMatches, id: &str, value: &T) -> Option<T> {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Merges the config file (if any) with the command line arguments.
+/// Arguments that were explicitly set take precedence over the config file.
+pub fn resolve_settings(args: &AppArgs, matches: &ArgMatches) -> Result<Settings, ConfigError> {
+    let file = match &args.config {
+        Some(path) => load_config_file(path)?,
+        None => ConfigFile::default(),
+    };
+
+    let cli_create_missing = explicit_arg(matches, "create_missing", &args.create_missing);
+    let cli_create_ttl = explicit_arg(matches, "create_ttl", &args.create_ttl);
+
+    let create_missing = cli_create_missing
+        .or(file.create_missing)
+        .unwrap_or(args.create_missing);
+    let explicit_create_ttl = cli_create_ttl.or(file.create_ttl);
+    let create_ttl = explicit_create_ttl.unwrap_or(args.create_ttl);
+
+    let cli_ttl = TtlPolicy {
+        ttl: explicit_arg(matches, "ttl", &args.ttl).flatten(),
+        min_ttl: explicit_arg(matches, "min_ttl", &args.min_ttl).flatten(),
+        max_ttl: explicit_arg(matches, "max_ttl", &args.max_ttl).flatten(),
+    };
+    let ttl = TtlPolicy {
+        ttl: cli_ttl.ttl.or(file.ttl),
+        min_ttl: cli_ttl.min_ttl.or(file.min_ttl),
+        max_ttl: cli_ttl.max_ttl.or(file.max_ttl),
+    };
+    ttl.validate()?;
+
+    let cli_ipv6_prefix_length =
+        explicit_arg(matches, "ipv6_prefix_length", &args.ipv6_prefix_length);
+    let ipv6_prefix_length = cli_ipv6_prefix_length
+        .or(file.ipv6_prefix_length)
+        .unwrap_or(args.ipv6_prefix_length);
+
+    if ipv6_prefix_length > 128 {
+        return Err(ConfigError::InvalidIpv6PrefixLength(ipv6_prefix_length));
+    }
+
+    let cli_ip_sources = explicit_arg(matches, "ip_sources", &args.ip_sources);
+    let ip_sources = cli_ip_sources
+        .clone()
+        .or(file.ip_sources)
+        .unwrap_or_else(|| args.ip_sources.clone());
+
+    let mut providers = BTreeMap::new();
+    let mut entries = vec![];
+
+    for (name, section) in file.providers {
+        if name == CLI_PROVIDER_NAME {
+            return Err(ConfigError::ReservedProviderName(name));
+        }
+
+        let config = section
+            .into_provider_config()
+            .map_err(|err| ConfigError::InvalidProvider(name.clone(), err))?;
+
+        providers.insert(name, config);
+    }
+
+    for (index, section) in file.records.into_iter().enumerate() {
+        // 1-based to match what users see in the file
+        let entry_number = index + 1;
+
+        if !providers.contains_key(&section.provider) {
+            return Err(ConfigError::UnknownProvider(entry_number, section.provider));
+        }
+
+        let default_family = match &section.families {
+            Some(families) => parse_families(entry_number, families)?,
+            None => None,
+        };
+
+        let domains = section
+            .domains
+            .iter()
+            .map(|domain| {
+                let mut domain_arg = domain.parse::<DomainArg>().map_err(|err| {
+                    ConfigError::InvalidDomain(entry_number, domain.clone(), err.to_string())
+                })?;
+                domain_arg.family = domain_arg.family.or(default_family);
+
+                Ok(domain_arg)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entry_create_missing = cli_create_missing
+            .or(section.create_missing)
+            .unwrap_or(create_missing);
+        let entry_ttl = TtlPolicy {
+            ttl: cli_ttl.ttl.or(section.ttl).or(ttl.ttl),
+            min_ttl: cli_ttl.min_ttl.or(section.min_ttl).or(ttl.min_ttl),
+            max_ttl: cli_ttl.max_ttl.or(section.max_ttl).or(ttl.max_ttl),
+        };
+        entry_ttl.validate()?;
+        let entry_ip_sources = cli_ip_sources
+            .clone()
+            .or(section.ip_sources)
+            .or(section
+                .ip_urls
+                .map(|ip_urls| vec![IpSourceSpec::Http(Some(ip_urls))]))
+            .unwrap_or_else(|| ip_sources.clone());
+
+        let entry_ipv6_prefix_length = cli_ipv6_prefix_length
+            .or(section.ipv6_prefix_length)
+            .unwrap_or(ipv6_prefix_length);
+
+        if entry_ipv6_prefix_length > 128 {
+            return Err(ConfigError::InvalidIpv6PrefixLength(
+                entry_ipv6_prefix_length,
+            ));
+        }
+
+        entries.push(RecordEntry {
+            provider: section.provider,
+            domains,
+            create_ttl: entry_create_missing.then(|| {
+                created_ttl(
+                    explicit_create_ttl,
+                    create_ttl,
+                    &entry_ttl,
+                    &format!("record entry {entry_number}"),
+                )
+            }),
+            ttl: entry_ttl,
+            ip_sources: entry_ip_sources,
+            ipv6_prefix_length: entry_ipv6_prefix_length,
+        });
+    }
+
+    // Domains on the command line are updated through the provider configured on the command line
+    if !args.domains.is_empty() {
+        let config = args
+            .provider_config()
+            .map_err(|err| ConfigError::InvalidProvider(CLI_PROVIDER_NAME.to_string(), err))?;
+
+        providers.insert(CLI_PROVIDER_NAME.to_string(), config);
+        entries.push(RecordEntry {
+            provider: CLI_PROVIDER_NAME.to_string(),
+            domains: args.domains.clone(),
+            create_ttl: create_missing
+                .then(|| created_ttl(explicit_create_ttl, create_ttl, &ttl, "--domain")),
+            ttl,
+            ip_sources,
+            ipv6_prefix_length,
+        });
+    }
+
+    if entries.iter().all(|entry| entry.domains.is_empty()) {
+        return Err(ConfigError::NoDomains);
+    }
+
+    let ip_quorum = explicit_arg(matches, "ip_quorum", &args.ip_quorum)
+        .flatten()
+        .or(file.ip_quorum);
+
+    if let Some(quorum) = ip_quorum {
+        let mut source_chains = vec![];
+
+        for entry in &entries {
+            if !source_chains.contains(&&entry.ip_sources) {
+                source_chains.push(&entry.ip_sources);
+            }
+        }
+
+        for sources in source_chains {
+            check_quorum(quorum.get(), sources)?;
+        }
+    }
+
+    Ok(Settings {
+        update_interval: explicit_arg(matches, "update_interval", &args.update_interval)
+            .flatten()
+            .or(file.update_interval),
+        watch_addresses: explicit_arg(matches, "watch_addresses", &args.watch_addresses)
+            .or(file.watch_addresses)
+            .unwrap_or(args.watch_addresses),
+        apply: explicit_arg(matches, "apply", &args.apply)
+            .or(file.apply)
+            .unwrap_or(args.apply),
+        skip_warning: explicit_arg(matches, "skip_warning", &args.skip_warning)
+            .or(file.skip_warning)
+            .unwrap_or(args.skip_warning),
+        state_file: explicit_arg(matches, "state_file", &args.state_file)
+            .or(file.state_file)
+            .unwrap_or_else(|| args.state_file.clone()),
+        max_staleness: explicit_arg(matches, "max_staleness", &args.max_staleness)
+            .or(file.max_staleness)
+            .unwrap_or(args.max_staleness),
+        ip_quorum,
+        ip_confirmations: explicit_arg(matches, "ip_confirmations", &args.ip_confirmations)
+            .or(file.ip_confirmations)
+            .unwrap_or(args.ip_confirmations),
+        ip_confirm_delay: Duration::from_secs(
+            explicit_arg(matches, "ip_confirm_delay", &args.ip_confirm_delay)
+                .or(file.ip_confirm_delay)
+                .unwrap_or(args.ip_confirm_delay),
+        ),
+        ip_confirm_duration: Duration::from_secs(
+            explicit_arg(matches, "ip_confirm_duration", &args.ip_confirm_duration)
+                .or(file.ip_confirm_duration)
+                .unwrap_or(args.ip_confirm_duration),
+        ),
+        address_policy: AddressPolicy {
+            denied: explicit_arg(matches, "denied_ranges", &args.denied_ranges)
+                .or(file.denied_ranges)
+                .unwrap_or_default(),
+            allowed: explicit_arg(matches, "allowed_ranges", &args.allowed_ranges)
+                .or(file.allowed_ranges)
+                .unwrap_or_default(),
+        },
+        providers,
+        entries,
+    })
+}
+
+/// Checks that `quorum` can be reached by the votes of `sources`, and warns if it's not a
+/// majority of them, since then a few sources can decide the WAN IP on their own.
+fn check_quorum(quorum: usize, sources: &[IpSourceSpec]) -> Result<(), ConfigError> {
+    let votes = sources
+        .iter()
+        .enumerate()
+        // A source that is listed twice only votes once
+        .filter(|(index, source)| !sources[..*index].contains(source))
+        .map(|(_, source)| source.votes())
+        .collect::<Vec<_>>();
+    // Sources that use api_urls.txt have at least one endpoint
+    let min_votes = votes.iter().map(|votes| votes.unwrap_or(1)).sum::<usize>();
+
+    if quorum > min_votes && votes.iter().all(Option::is_some) {
+        return Err(ConfigError::UnreachableQuorum(quorum, min_votes));
+    }
+
+    if quorum * 2 <= min_votes {
+        log::warn!(
+            "An IP quorum of {quorum} is not a majority of the {min_votes} WAN IP sources, so a minority of them can decide the WAN IP"
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the TTL that missing records of `entry` are created with. The TTL policy wins over the
+/// create TTL, so an explicitly set create TTL that it changes is warned about.
+fn created_ttl(
+    explicit_create_ttl: Option<u32>,
+    create_ttl: u32,
+    ttl: &TtlPolicy,
+    entry: &str,
+) -> u32 {
+    let desired = ttl.desired(create_ttl);
+
+    if explicit_create_ttl.is_some() && desired != create_ttl {
+        log::warn!(
+            "The records created for {entry} get the TTL {desired} of --ttl/--min-ttl/--max-ttl instead of the --create-ttl {create_ttl}"
+        );
+    }
+
+    desired
+}
+
+/// Parses the `families` of a record entry. Returns `None` if both families are included.
+fn parse_families(
+    entry_number: usize,
+    families: &[String],
+) -> Result<Option<IpFamily>, ConfigError> {
+    if families.is_empty() {
+        return Err(ConfigError::EmptyFamilies(entry_number));
+    }
+
+    let mut parsed = vec![];
+
+    for family in families {
+        parsed.push(match family.to_uppercase().as_str() {
+            "A" => IpFamily::V4,
+            "AAAA" => IpFamily::V6,
+            _ => return Err(ConfigError::InvalidFamily(entry_number, family.clone())),
+        });
+    }
+
+    match parsed.as_slice() {
+        [family] => Ok(Some(*family)),
+        [first, rest @ ..] if rest.iter().all(|family| family == first) => Ok(Some(*first)),
+        _ => Ok(None),
+    }
+}
+
+impl ProviderSection {
+    fn into_provider_config(self) -> Result<ProviderConfig, NewProviderError> {
+        Ok(match self {
+            ProviderSection::DigitalOcean { api_key, api_base } => ProviderConfig::DigitalOcean {
+                api_key,
+                api_base: api_base.unwrap_or_else(|| digitalocean::DEFAULT_API_BASE.to_string()),
+            },
+            ProviderSection::Cloudflare {
+                api_token,
+                api_base,
+            } => ProviderConfig::Cloudflare {
+                api_token,
+                api_base: api_base.unwrap_or_else(|| cloudflare::DEFAULT_API_BASE.to_string()),
+            },
+            ProviderSection::Rfc2136 {
+                server,
+                zones,
+                tcp,
+                tsig_key_name,
+                tsig_algorithm,
+                tsig_secret,
+            } => ProviderConfig::Rfc2136(Rfc2136Config {
+                server,
+                zones,
+                tcp,
+                tsig_key: TsigKey::from_base64(
+                    tsig_key_name.as_deref(),
+                    tsig_algorithm.unwrap_or(TsigAlgorithmArg::HmacSha256),
+                    tsig_secret.as_deref(),
+                )?,
+                timeout: rfc2136::DEFAULT_TIMEOUT,
+            }),
+        })
+    }
+}
@@ -1,7 +1,22 @@
-use reqwest::{header::HeaderMap, Client, ClientBuilder, Method, Response, StatusCode};
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use reqwest::{
+    header::HeaderMap, Client, ClientBuilder, Method, RequestBuilder, Response, StatusCode,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use crate::{
+    provider::{DnsProvider, DnsRecord, ProviderError, Zone},
+    retry::RetryPolicy,
+};
+
 #[derive(Debug, Deserialize)]
+// Not every field is used by the updater, but they're kept to mirror the API
+#[allow(dead_code)]
 pub struct Record {
     /// A unique identifier for each domain record.
     pub id: i32,
@@ -53,11 +68,66 @@ pub struct ErrorResponse {
 #[derive(Debug, Deserialize)]
 pub struct ListDomainRecordsResponse {
     pub domain_records: Vec<Record>,
+    #[serde(default)]
+    pub links: Links,
+    pub meta: Option<Meta>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ListAllDomainsResponse {
     pub domains: Vec<Domain>,
+    #[serde(default)]
+    pub links: Links,
+    pub meta: Option<Meta>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Links {
+    pub pages: Option<Pages>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pages {
+    /// Absolute url of the next page, missing on the last page.
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Meta {
+    /// The total number of items across all pages.
+    pub total: usize,
+}
+
+/// A response from a list endpoint that may be split into multiple pages.
+trait PaginatedResponse {
+    type Item;
+
+    /// Returns the items of this page, the url of the next page and the total number of items.
+    fn into_page(self) -> (Vec<Self::Item>, Option<String>, Option<usize>);
+}
+
+impl PaginatedResponse for ListDomainRecordsResponse {
+    type Item = Record;
+
+    fn into_page(self) -> (Vec<Record>, Option<String>, Option<usize>) {
+        (
+            self.domain_records,
+            self.links.pages.and_then(|pages| pages.next),
+            self.meta.map(|meta| meta.total),
+        )
+    }
+}
+
+impl PaginatedResponse for ListAllDomainsResponse {
+    type Item = Domain;
+
+    fn into_page(self) -> (Vec<Domain>, Option<String>, Option<usize>) {
+        (
+            self.domains,
+            self.links.pages.and_then(|pages| pages.next),
+            self.meta.map(|meta| meta.total),
+        )
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -65,6 +135,7 @@ struct UpdateRecordRequestData<'a> {
     #[serde(rename = "type")]
     ty: &'a str,
     data: &'a str,
+    ttl: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -72,11 +143,31 @@ struct UpdateRecordResponseData {
     pub domain_record: Record,
 }
 
+#[derive(Debug, Serialize)]
+struct CreateRecordRequestData<'a> {
+    #[serde(rename = "type")]
+    ty: &'a str,
+    name: &'a str,
+    data: &'a str,
+    ttl: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRecordResponseData {
+    pub domain_record: Record,
+}
+
 pub struct DigitalOcean {
     client: Client,
+    api_base: String,
+    retry_policy: RetryPolicy,
+    /// When the rate limit resets, if the last response said there are no requests left.
+    rate_limit_reset: Mutex<Option<SystemTime>>,
 }
 
-const API_BASE: &str = "https://api.digitalocean.com";
+pub const DEFAULT_API_BASE: &str = "https://api.digitalocean.com";
+/// The maximum page size allowed by the API.
+const PER_PAGE: u32 = 200;
 
 #[derive(Debug, thiserror::Error)]
 pub enum QueryError {
@@ -115,7 +206,9 @@ impl From<reqwest::Error> for NewClientError {
 }
 
 impl DigitalOcean {
-    pub fn new(api_key: String) -> Result<Self, NewClientError> {
+    /// Creates a new client that authenticates with an API key.
+    /// `api_base` is normally [`DEFAULT_API_BASE`], but can be pointed at a mock server.
+    pub fn new(api_key: String, api_base: &str) -> Result<Self, NewClientError> {
         let mut default_headers = HeaderMap::new();
         default_headers.insert(
             "Authorization",
@@ -134,26 +227,23 @@ impl DigitalOcean {
             client: ClientBuilder::new()
                 .default_headers(default_headers)
                 .build()
-                .map_err(|err| NewClientError::ReqwestError(err))?,
+                .map_err(NewClientError::ReqwestError)?,
+            api_base: api_base.trim_end_matches('/').to_string(),
+            retry_policy: RetryPolicy::default(),
+            rate_limit_reset: Mutex::new(None),
         })
     }
 
-    /// Note: Technically only queries the first 200 domains. Will fix if requested.
     pub async fn list_all_domains(&self) -> Result<Vec<Domain>, QueryError> {
-        Ok(self
-            .make_request::<ListAllDomainsResponse>("/v2/domains?per_page=200", Method::GET)
-            .await?
-            .domains)
+        self.get_all_pages::<ListAllDomainsResponse>(&format!("/v2/domains?per_page={PER_PAGE}"))
+            .await
     }
 
     pub async fn query_domain_records(&self, domain_name: &str) -> Result<Vec<Record>, QueryError> {
-        Ok(self
-            .make_request::<ListDomainRecordsResponse>(
-                &format!("/v2/domains/{domain_name}/records"),
-                Method::GET,
-            )
-            .await?
-            .domain_records)
+        self.get_all_pages::<ListDomainRecordsResponse>(&format!(
+            "/v2/domains/{domain_name}/records?per_page={PER_PAGE}"
+        ))
+        .await
     }
 
     pub async fn update_record(
@@ -162,6 +252,7 @@ impl DigitalOcean {
         record_id: i32,
         new_type: &str,
         new_value: &str,
+        ttl: u32,
     ) -> Result<Record, QueryError> {
         let path = format!("/v2/domains/{domain_name}/records/{record_id}");
 
@@ -172,21 +263,96 @@ impl DigitalOcean {
                 &UpdateRecordRequestData {
                     data: new_value,
                     ty: new_type,
+                    ttl,
+                },
+            )
+            .await?
+            .domain_record)
+    }
+
+    pub async fn create_record(
+        &self,
+        domain_name: &str,
+        name: &str,
+        ty: &str,
+        value: &str,
+        ttl: u32,
+    ) -> Result<Record, QueryError> {
+        let path = format!("/v2/domains/{domain_name}/records");
+
+        Ok(self
+            .make_request_with_data::<_, CreateRecordResponseData>(
+                &path,
+                Method::POST,
+                &CreateRecordRequestData {
+                    ty,
+                    name,
+                    data: value,
+                    ttl,
                 },
             )
             .await?
             .domain_record)
     }
 
-    async fn make_request<ResponseData: DeserializeOwned>(
+    pub async fn delete_record(&self, domain_name: &str, record_id: i32) -> Result<(), QueryError> {
+        let path = format!(
+            "{}/v2/domains/{domain_name}/records/{record_id}",
+            self.api_base
+        );
+        let response = self
+            .send(self.client.request(Method::DELETE, &path), true)
+            .await?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            _ => Self::handle_response::<serde_json::Value>(response)
+                .await
+                .map(|_| ()),
+        }
+    }
+
+    /// Follows the `links.pages.next` urls until every item has been fetched.
+    async fn get_all_pages<ResponseData: DeserializeOwned + PaginatedResponse>(
         &self,
         path: &str,
+    ) -> Result<Vec<ResponseData::Item>, QueryError> {
+        let mut items = vec![];
+        let mut url = format!("{}{}", self.api_base, path);
+
+        loop {
+            let (page_items, next_url, total) = self
+                .make_request_to_url::<ResponseData>(&url, Method::GET)
+                .await?
+                .into_page();
+            let page_was_empty = page_items.is_empty();
+
+            items.extend(page_items);
+
+            match next_url {
+                // Stop on empty pages and when the total is reached to avoid looping forever
+                // if the api returns a bogus next link
+                Some(next_url)
+                    if !page_was_empty && total.is_none_or(|total| items.len() < total) =>
+                {
+                    url = next_url
+                }
+                _ => break,
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn make_request_to_url<ResponseData: DeserializeOwned>(
+        &self,
+        url: &str,
         method: Method,
     ) -> Result<ResponseData, QueryError> {
-        let path = format!("{}{}", API_BASE, path);
-        let builder = self.client.request(method, &path);
+        let idempotent = method != Method::POST;
+        let builder = self.client.request(method, url);
 
-        let response = builder.send().await?;
+        let response = self.send(builder, idempotent).await?;
 
         Self::handle_response(response).await
     }
@@ -197,20 +363,118 @@ impl DigitalOcean {
         method: Method,
         data: &RequestData,
     ) -> Result<ResponseData, QueryError> {
-        let path = format!("{}{}", API_BASE, path);
-        let builder = self.client.request(method, &path);
-        let response = builder.json(data).send().await?;
+        let path = format!("{}{}", self.api_base, path);
+        let idempotent = method != Method::POST;
+        let builder = self.client.request(method, &path).json(data);
+        let response = self.send(builder, idempotent).await?;
 
         Self::handle_response(response).await
     }
 
+    /// Sends a request, retrying with backoff on rate limits, server errors and network errors.
+    /// Requests that aren't idempotent are only retried if the API can't have processed them.
+    async fn send(
+        &self,
+        builder: RequestBuilder,
+        idempotent: bool,
+    ) -> Result<Response, QueryError> {
+        let mut retry = 0;
+
+        loop {
+            self.wait_for_rate_limit_reset().await;
+
+            let result = builder
+                .try_clone()
+                .expect("Requests with json bodies should always be cloneable")
+                .send()
+                .await;
+
+            let backoff = self.retry_policy.backoff(retry);
+            let (delay, reason) = match &result {
+                Ok(response) => {
+                    self.track_rate_limit(response.headers());
+
+                    match response.status() {
+                        StatusCode::TOO_MANY_REQUESTS => (
+                            Some(requested_delay(response.headers()).unwrap_or(backoff)),
+                            "rate limited".to_string(),
+                        ),
+                        status if status.is_server_error() && idempotent => {
+                            (Some(backoff), status.to_string())
+                        }
+                        _ => (None, String::new()),
+                    }
+                }
+                Err(err) if err.is_connect() || (idempotent && err.is_timeout()) => {
+                    (Some(backoff), err.to_string())
+                }
+                Err(_) => (None, String::new()),
+            };
+
+            match delay {
+                Some(delay)
+                    if retry < self.retry_policy.max_retries
+                        && delay <= self.retry_policy.max_requested_delay =>
+                {
+                    retry += 1;
+
+                    log::warn!(
+                        "DigitalOcean request failed ({reason}), retrying in {:.1} seconds ({retry}/{})",
+                        delay.as_secs_f32(),
+                        self.retry_policy.max_retries,
+                    );
+
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Ok(result?),
+            }
+        }
+    }
+
+    /// Remembers when the rate limit resets if there are no requests left.
+    fn track_rate_limit(&self, headers: &HeaderMap) {
+        let reset = match header_u64(headers, "RateLimit-Remaining") {
+            Some(0) => header_u64(headers, "RateLimit-Reset")
+                .map(|reset| UNIX_EPOCH + Duration::from_secs(reset)),
+            _ => None,
+        };
+
+        *self
+            .rate_limit_reset
+            .lock()
+            .expect("Rate limit lock should never be poisoned") = reset;
+    }
+
+    /// Waits until the rate limit resets if the last response said there are no requests left,
+    /// unless that is further away than the retry policy allows.
+    async fn wait_for_rate_limit_reset(&self) {
+        let reset = *self
+            .rate_limit_reset
+            .lock()
+            .expect("Rate limit lock should never be poisoned");
+
+        let Some(wait) = reset.and_then(|reset| reset.duration_since(SystemTime::now()).ok())
+        else {
+            return;
+        };
+
+        if wait <= self.retry_policy.max_requested_delay {
+            log::info!(
+                "DigitalOcean rate limit reached, waiting {} seconds for it to reset",
+                wait.as_secs()
+            );
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
     async fn handle_response<ResponseData: DeserializeOwned>(
         response: Response,
     ) -> Result<ResponseData, QueryError> {
         let status_code = response.status();
 
         match status_code {
-            StatusCode::OK => Ok(response.json().await?),
+            StatusCode::OK | StatusCode::CREATED => Ok(response.json().await?),
             StatusCode::UNAUTHORIZED
             | StatusCode::NOT_FOUND
             | StatusCode::TOO_MANY_REQUESTS
@@ -231,3 +495,120 @@ impl DigitalOcean {
         }
     }
 }
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Returns how long a rate limited response asked us to wait before retrying.
+/// `Retry-After` is in seconds, `RateLimit-Reset` is the unix time when the limit resets.
+fn requested_delay(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(seconds) = header_u64(headers, "Retry-After") {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let reset = UNIX_EPOCH + Duration::from_secs(header_u64(headers, "RateLimit-Reset")?);
+
+    Some(
+        reset
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+impl From<Domain> for Zone {
+    fn from(domain: Domain) -> Self {
+        Zone {
+            id: domain.name.clone(),
+            name: domain.name,
+        }
+    }
+}
+
+impl From<Record> for DnsRecord {
+    fn from(record: Record) -> Self {
+        DnsRecord {
+            id: record.id.to_string(),
+            ty: record.ty,
+            name: record.name,
+            data: record.data,
+            ttl: record.ttl.max(0) as u32,
+            proxied: None,
+        }
+    }
+}
+
+fn parse_record_id(record: &DnsRecord) -> Result<i32, ProviderError> {
+    record.id.parse().map_err(|_| {
+        ProviderError::Other(anyhow::anyhow!(
+            "Invalid DigitalOcean record id: {}",
+            record.id
+        ))
+    })
+}
+
+#[async_trait]
+impl DnsProvider for DigitalOcean {
+    fn name(&self) -> &'static str {
+        "DigitalOcean"
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        Ok(self
+            .list_all_domains()
+            .await?
+            .into_iter()
+            .map(Zone::from)
+            .collect())
+    }
+
+    async fn list_records(
+        &self,
+        zone: &Zone,
+        _names: &[&str],
+    ) -> Result<Vec<DnsRecord>, ProviderError> {
+        Ok(self
+            .query_domain_records(&zone.name)
+            .await?
+            .into_iter()
+            .map(DnsRecord::from)
+            .collect())
+    }
+
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &DnsRecord,
+        new_value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        let record_id = parse_record_id(record)?;
+
+        Ok(
+            DigitalOcean::update_record(self, &zone.name, record_id, &record.ty, new_value, ttl)
+                .await?
+                .into(),
+        )
+    }
+
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        name: &str,
+        ty: &str,
+        value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        Ok(
+            DigitalOcean::create_record(self, &zone.name, name, ty, value, ttl)
+                .await?
+                .into(),
+        )
+    }
+
+    async fn delete_record(&self, zone: &Zone, record: &DnsRecord) -> Result<(), ProviderError> {
+        let record_id = parse_record_id(record)?;
+
+        Ok(DigitalOcean::delete_record(self, &zone.name, record_id).await?)
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    interface_id::{InterfaceId, ParseInterfaceIdError},
+    wan_ip_query::IpFamily,
+};
+
+/// A domain specified on the command line, optionally restricted to a single record type.
+///
+/// `home.example.com` updates both the A and AAAA records, `home.example.com:A` only updates
+/// the A record and `home.example.com:AAAA` only updates the AAAA record.
+/// `nas.example.com:AAAA=::10` sets the AAAA record to the prefix of the WAN IPv6 address
+/// followed by the given interface identifier, for hosts on the LAN. A domain with an interface
+/// identifier only has an AAAA record, so `nas.example.com=::10` is the same as the above.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DomainArg {
+    /// The fully qualified domain name.
+    pub name: String,
+    /// The only address family to update. If `None` both families are updated when possible.
+    pub family: Option<IpFamily>,
+    /// The interface identifier of the host, if the AAAA record isn't the WAN IPv6 address itself.
+    pub interface_id: Option<InterfaceId>,
+}
+
+impl DomainArg {
+    /// Returns the address families that should be updated for this domain.
+    pub fn families(&self) -> Vec<IpFamily> {
+        match self.family {
+            Some(family) => vec![family],
+            None => IpFamily::ALL.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseDomainArgError {
+    #[error("Domain name is empty")]
+    EmptyName,
+    #[error("Unknown record type \"{0}\", expected A or AAAA")]
+    UnknownRecordType(String),
+    #[error(transparent)]
+    InvalidInterfaceId(ParseInterfaceIdError),
+    #[error("An interface identifier can only be used for AAAA records")]
+    InterfaceIdWithoutIpv6,
+}
+
+impl FromStr for DomainArg {
+    type Err = ParseDomainArgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, interface_id) = match s.split_once('=') {
+            Some((s, interface_id)) => (
+                s,
+                Some(
+                    interface_id
+                        .parse::<InterfaceId>()
+                        .map_err(ParseDomainArgError::InvalidInterfaceId)?,
+                ),
+            ),
+            None => (s, None),
+        };
+
+        let (name, family) = match s.split_once(':') {
+            Some((name, ty)) => {
+                let family = match ty.to_uppercase().as_str() {
+                    "A" => IpFamily::V4,
+                    "AAAA" => IpFamily::V6,
+                    _ => return Err(ParseDomainArgError::UnknownRecordType(ty.to_string())),
+                };
+
+                (name, Some(family))
+            }
+            None => (s, None),
+        };
+
+        if name.is_empty() {
+            return Err(ParseDomainArgError::EmptyName);
+        }
+
+        let family = match (&interface_id, family) {
+            (Some(_), Some(IpFamily::V4)) => {
+                return Err(ParseDomainArgError::InterfaceIdWithoutIpv6)
+            }
+            // The WAN IPv4 isn't the address of the host, so only its AAAA record is updated
+            (Some(_), _) => Some(IpFamily::V6),
+            (None, family) => family,
+        };
+
+        Ok(DomainArg {
+            name: name.to_string(),
+            family,
+            interface_id,
+        })
+    }
+}
+
+impl Display for DomainArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.family {
+            Some(family) => write!(f, "{} ({})", self.name, family.record_type()),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
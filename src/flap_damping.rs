@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+/// Holds back WAN IP changes until the new IPs were seen in enough consecutive queries (and for
+/// long enough), so an address that an ISP only hands out briefly during a reconnect isn't
+/// published.
+#[derive(Debug)]
+pub struct FlapDamper<K> {
+    confirmations: NonZeroU32,
+    min_duration: Duration,
+    /// The IPs that were last accepted.
+    accepted: HashMap<K, IpAddr>,
+    /// The changed IPs that aren't confirmed yet.
+    pending: Option<PendingChange<K>>,
+}
+
+#[derive(Debug)]
+struct PendingChange<K> {
+    ips: HashMap<K, IpAddr>,
+    /// How often the change was seen in a row.
+    seen: u32,
+    /// When the change was first seen.
+    since: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The IPs can be used.
+    Accepted,
+    /// Some IPs changed, and the change was seen `seen` of the `needed` times. It has to be seen
+    /// for `remaining` more time before it's accepted.
+    Pending {
+        seen: u32,
+        needed: u32,
+        remaining: Duration,
+    },
+}
+
+impl<K: Hash + Eq + Clone> FlapDamper<K> {
+    pub fn new(confirmations: NonZeroU32, min_duration: Duration) -> Self {
+        FlapDamper {
+            confirmations,
+            min_duration,
+            accepted: HashMap::new(),
+            pending: None,
+        }
+    }
+
+    /// Treats `ips` as accepted, like the IPs that the records were set to before a restart,
+    /// so that a change from them also has to be confirmed.
+    pub fn accept(&mut self, ips: HashMap<K, IpAddr>) {
+        self.accepted.extend(ips);
+    }
+
+    /// Records the IPs of a query made now, see [`FlapDamper::observe_at`].
+    pub fn observe(&mut self, ips: &HashMap<K, IpAddr>) -> Verdict {
+        self.observe_at(ips, Instant::now())
+    }
+
+    /// Records the IPs of a query made at `now`. They're accepted if none of them differs from
+    /// the accepted IPs, or if the same changes were seen `confirmations` times in a row over at
+    /// least the minimum duration. IPs that weren't known before, like on the first query
+    /// without a state, aren't changes.
+    pub fn observe_at(&mut self, ips: &HashMap<K, IpAddr>, now: Instant) -> Verdict {
+        let changes = ips
+            .iter()
+            .filter(|(key, ip)| {
+                self.accepted
+                    .get(key)
+                    .is_some_and(|accepted| accepted != *ip)
+            })
+            .map(|(key, ip)| (key.clone(), *ip))
+            .collect::<HashMap<_, _>>();
+
+        if !changes.is_empty() {
+            let (seen, since) = match self.pending.take() {
+                Some(pending) if pending.ips == changes => (pending.seen + 1, pending.since),
+                // Changing to yet another IP starts over
+                _ => (1, now),
+            };
+            let remaining = self
+                .min_duration
+                .saturating_sub(now.saturating_duration_since(since));
+
+            if seen < self.confirmations.get() || !remaining.is_zero() {
+                self.pending = Some(PendingChange {
+                    ips: changes,
+                    seen,
+                    since,
+                });
+
+                return Verdict::Pending {
+                    seen,
+                    needed: self.confirmations.get(),
+                    remaining,
+                };
+            }
+        }
+
+        self.pending = None;
+        self.accepted
+            .extend(ips.iter().map(|(key, ip)| (key.clone(), *ip)));
+
+        Verdict::Accepted
+    }
+}
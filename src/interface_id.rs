@@ -0,0 +1,85 @@
+use std::{fmt::Display, net::Ipv6Addr, str::FromStr};
+
+/// The host part of an IPv6 address, combined with the (delegated) prefix of the WAN IPv6
+/// address to get the address of a host on the LAN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterfaceId {
+    /// A static suffix, like `::10` or `::211:22ff:fe33:4455`.
+    Suffix(Ipv6Addr),
+    /// The modified EUI-64 identifier (RFC 4291 appendix A) derived from a MAC address,
+    /// which is what hosts use when SLAAC privacy extensions are disabled.
+    Eui64([u8; 6]),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid interface identifier \"{0}\", expected an IPv6 suffix like ::10 or a MAC address")]
+pub struct ParseInterfaceIdError(String);
+
+impl InterfaceId {
+    /// Returns the last 64 bits of the address, or all bits for a suffix.
+    fn bits(&self) -> u128 {
+        match self {
+            InterfaceId::Suffix(suffix) => u128::from(*suffix),
+            InterfaceId::Eui64(mac) => {
+                // The universal/local bit is inverted, and ff:fe is inserted in the middle
+                let id = [
+                    mac[0] ^ 0x02,
+                    mac[1],
+                    mac[2],
+                    0xff,
+                    0xfe,
+                    mac[3],
+                    mac[4],
+                    mac[5],
+                ];
+
+                u64::from_be_bytes(id) as u128
+            }
+        }
+    }
+
+    /// Replaces everything after the first `prefix_length` bits of `prefix` with this identifier.
+    pub fn apply(&self, prefix: Ipv6Addr, prefix_length: u8) -> Ipv6Addr {
+        let prefix_mask = u128::MAX
+            .checked_shl(128 - u32::from(prefix_length.min(128)))
+            .unwrap_or(0);
+
+        Ipv6Addr::from((u128::from(prefix) & prefix_mask) | (self.bits() & !prefix_mask))
+    }
+}
+
+impl FromStr for InterfaceId {
+    type Err = ParseInterfaceIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(suffix) = s.parse::<Ipv6Addr>() {
+            return Ok(InterfaceId::Suffix(suffix));
+        }
+
+        let octets = s
+            .split([':', '-'])
+            .map(|octet| match octet.len() {
+                2 => u8::from_str_radix(octet, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match octets.map(<[u8; 6]>::try_from) {
+            Some(Ok(mac)) => Ok(InterfaceId::Eui64(mac)),
+            _ => Err(ParseInterfaceIdError(s.to_string())),
+        }
+    }
+}
+
+impl Display for InterfaceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterfaceId::Suffix(suffix) => write!(f, "{suffix}"),
+            InterfaceId::Eui64(mac) => write!(
+                f,
+                "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+            ),
+        }
+    }
+}
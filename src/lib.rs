@@ -0,0 +1,252 @@
+pub mod address_policy;
+pub mod address_watch;
+pub mod cloudflare;
+pub mod config;
+pub mod digitalocean;
+pub mod domain_arg;
+pub mod flap_damping;
+pub mod interface_id;
+pub mod provider;
+pub mod retry;
+pub mod rfc2136;
+pub mod state;
+pub mod updater;
+pub mod wan_ip_query;
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+};
+
+use address_policy::RejectedAddress;
+use anyhow::Result;
+use config::ConfigError;
+use domain_arg::DomainArg;
+use ipnet::IpNet;
+use provider::{NewProviderError, ProviderConfig, ProviderKind};
+use rfc2136::{Rfc2136Config, TsigAlgorithmArg, TsigKey};
+use wan_ip_query::{IpSourceSpec, WanIpError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("The {0} key is invalid")]
+    TestFailedKeyValidation(&'static str),
+    #[error("Failed to query WAN IP: {0}")]
+    TestFailedToQueryWanIp(WanIpError),
+    #[error("The WAN IP can't be published: {0}")]
+    RejectedWanIp(RejectedAddress),
+    #[error("An unexpected error occurred: {0}")]
+    OtherError(anyhow::Error),
+    #[error("Invalid provider configuration: {0}")]
+    InvalidProviderConfig(NewProviderError),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(ConfigError),
+}
+
+// Generic error handling
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        AppError::OtherError(err)
+    }
+}
+
+impl From<wan_ip_query::WanIpError> for AppError {
+    fn from(err: wan_ip_query::WanIpError) -> Self {
+        AppError::TestFailedToQueryWanIp(err)
+    }
+}
+
+impl From<NewProviderError> for AppError {
+    fn from(err: NewProviderError) -> Self {
+        AppError::InvalidProviderConfig(err)
+    }
+}
+
+impl From<ConfigError> for AppError {
+    fn from(err: ConfigError) -> Self {
+        AppError::InvalidConfig(err)
+    }
+}
+
+impl AppError {
+    pub fn error_code(&self) -> i32 {
+        match self {
+            AppError::TestFailedKeyValidation(_) => 1,
+            AppError::TestFailedToQueryWanIp(_) => 2,
+            AppError::OtherError(_) => 3,
+            AppError::RejectedWanIp(_) => 4,
+            AppError::InvalidProviderConfig(_) => 5,
+            AppError::InvalidConfig(_) => 6,
+        }
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct AppArgs {
+    /// Path to a toml config file with providers and per-record settings.
+    /// Arguments that are specified on the command line take precedence over the config file
+    #[clap(short('C'), long, env = "DNS_UPDATER_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// The DNS provider that hosts the domains
+    #[clap(short('p'), long, env, value_enum, default_value_t = ProviderKind::DigitalOcean)]
+    pub provider: ProviderKind,
+    /// API key for DigitalOcean
+    #[clap(short('a'), long("api-key"), env, hide_env_values = true)]
+    // hide_env_values = true to avoid leaking secrets
+    pub do_api_key: Option<String>,
+    /// Base URL of the DigitalOcean API, for example to use a mock server while testing
+    #[clap(long, env, default_value = digitalocean::DEFAULT_API_BASE)]
+    pub do_api_base: String,
+    /// API token for Cloudflare. Needs the Zone:Read and DNS:Edit permissions
+    #[clap(long("cloudflare-api-token"), env, hide_env_values = true)]
+    pub cloudflare_api_token: Option<String>,
+    /// Base URL of the Cloudflare API, for example to use a mock server while testing
+    #[clap(long, env, default_value = cloudflare::DEFAULT_API_BASE)]
+    pub cf_api_base: String,
+    /// The authoritative name server to send RFC 2136 updates to, as host[:port]
+    #[clap(long, env)]
+    pub rfc2136_server: Option<String>,
+    /// The zones that the RFC 2136 server is the primary server for
+    #[clap(long("rfc2136-zone"), env, value_delimiter = ',')]
+    pub rfc2136_zones: Vec<String>,
+    /// Send RFC 2136 updates over TCP instead of UDP
+    #[clap(default_value_t = false, long, env)]
+    pub rfc2136_tcp: bool,
+    /// Name of the TSIG key used to sign RFC 2136 updates
+    #[clap(long, env)]
+    pub tsig_key_name: Option<String>,
+    /// Algorithm of the TSIG key
+    #[clap(long, env, value_enum, default_value_t = TsigAlgorithmArg::HmacSha256)]
+    pub tsig_algorithm: TsigAlgorithmArg,
+    /// Base64 encoded secret of the TSIG key
+    #[clap(long, env, hide_env_values = true)]
+    pub tsig_secret: Option<String>,
+    /// How often (in minutes) to check WAN IP and update records.
+    /// If unset the records will only be updated once and then the program will exit
+    #[clap(short('m'), long, allow_negative_numbers(false), env)]
+    pub update_interval: Option<i64>,
+    /// Update the records as soon as the addresses of the local network interfaces change,
+    /// like after a PPPoE reconnect. The update interval is still used to catch changes that
+    /// aren't visible locally. Keeps running even without an update interval
+    #[clap(default_value_t = false, short('w'), long, env)]
+    pub watch_addresses: bool,
+    /// If this flag is **NOT** set the program will only validate that the specified
+    /// domain records exist as A/AAAA records for the WAN IPv4/IPv6 address.
+    /// It will also preview the changes that would be made
+    #[clap(default_value_t = false, short('A'), long, env)]
+    pub apply: bool,
+    /// List of fully qualified domain names to update the values for.
+    /// Both the A and AAAA records are updated unless the name is suffixed with `:A` or `:AAAA`.
+    /// A `=<interface identifier>` suffix sets the AAAA record to the WAN IPv6 prefix with that identifier.
+    /// Required unless the domains are specified in the config file
+    #[clap(short('d'), long("domain"), env, value_delimiter = ',')]
+    pub domains: Vec<DomainArg>,
+    /// Create records that don't exist yet instead of reporting them as missing
+    #[clap(default_value_t = false, short('c'), long, env)]
+    pub create_missing: bool,
+    /// TTL (in seconds) of the records created by --create-missing.
+    /// If --ttl, --min-ttl or --max-ttl ask for another TTL, that one is used instead
+    #[clap(default_value_t = 300, long, env)]
+    pub create_ttl: u32,
+    /// TTL (in seconds) that the records should have. Records with another TTL are updated,
+    /// and records created by --create-missing get it too
+    #[clap(long, env)]
+    pub ttl: Option<u32>,
+    /// Raise the TTL of records with a lower TTL to this value
+    #[clap(long, env)]
+    pub min_ttl: Option<u32>,
+    /// Lower the TTL of records with a higher TTL to this value, like 300 for records that were
+    /// created with a TTL of 3600
+    #[clap(long, env)]
+    pub max_ttl: Option<u32>,
+    /// How many bits of the WAN IPv6 address are kept for domains with an interface identifier
+    /// (like `nas.example.com:AAAA=::10`), the rest is replaced by the identifier
+    #[clap(default_value_t = 64, long, env, value_parser = clap::value_parser!(u8).range(0..=128))]
+    pub ipv6_prefix_length: u8,
+    /// File that remembers the WAN IP and records of the last update, so the DNS providers
+    /// are only queried when the WAN IP changes
+    #[clap(long, env, default_value = state::DEFAULT_STATE_FILE)]
+    pub state_file: PathBuf,
+    /// How long (in minutes) the records in the state file are trusted before they are verified
+    /// with the DNS provider again, even if the WAN IP hasn't changed. 0 always queries the provider
+    #[clap(default_value_t = 1440, long, allow_negative_numbers(false), env)]
+    pub max_staleness: i64,
+    /// Where to get the WAN IP from, repeated for each source. Sources are tried in order until
+    /// one of them succeeds. `http` queries the endpoints in api_urls.txt and `http:<url>` queries
+    /// a specific endpoint
+    #[clap(long("ip-source"), env, default_value = "http")]
+    pub ip_sources: Vec<IpSourceSpec>,
+    /// Query every WAN IP source (and every endpoint of a source) at once, and only accept an
+    /// IP that at least this many of them agree on
+    #[clap(long, env)]
+    pub ip_quorum: Option<NonZeroUsize>,
+    /// How many consecutive queries have to report a changed WAN IP before the records are
+    /// changed, so addresses that are only assigned briefly during a reconnect aren't published
+    #[clap(default_value = "1", long, env)]
+    pub ip_confirmations: NonZeroU32,
+    /// How long (in seconds) to wait before querying a changed WAN IP again to confirm it
+    #[clap(default_value_t = 60, long, env)]
+    pub ip_confirm_delay: u64,
+    /// How long (in seconds) a changed WAN IP has to be reported for before the records are
+    /// changed, in addition to the confirmations. 0 only counts the confirmations
+    #[clap(default_value_t = 0, long, env)]
+    pub ip_confirm_duration: u64,
+    /// Reject WAN IPs in these ranges (like `87.128.0.0/24`), in addition to the reserved ranges
+    /// that are always rejected (private, loopback, link-local, CG-NAT, documentation, ULA, ...)
+    #[clap(long("deny-range"), env, value_delimiter = ',')]
+    pub denied_ranges: Vec<IpNet>,
+    /// Accept WAN IPs in these reserved ranges, like `10.0.0.0/8` for records on an internal DNS server
+    #[clap(long("allow-range"), env, value_delimiter = ',')]
+    pub allowed_ranges: Vec<IpNet>,
+    /// If this flag is set the 10 second warning on startup will not be shown before applying record changes.
+    #[clap(default_value_t = false, short('S'), long, env)]
+    pub skip_warning: bool,
+}
+
+impl AppArgs {
+    /// Builds the configuration of the selected provider from the arguments.
+    pub fn provider_config(&self) -> Result<ProviderConfig, NewProviderError> {
+        let kind = self.provider;
+
+        match kind {
+            ProviderKind::DigitalOcean => Ok(ProviderConfig::DigitalOcean {
+                api_key: self
+                    .do_api_key
+                    .clone()
+                    .ok_or(NewProviderError::MissingApiKey(kind))?,
+                api_base: self.do_api_base.clone(),
+            }),
+            ProviderKind::Cloudflare => Ok(ProviderConfig::Cloudflare {
+                api_token: self
+                    .cloudflare_api_token
+                    .clone()
+                    .ok_or(NewProviderError::MissingApiKey(kind))?,
+                api_base: self.cf_api_base.clone(),
+            }),
+            ProviderKind::Rfc2136 => {
+                let server = self
+                    .rfc2136_server
+                    .clone()
+                    .ok_or(NewProviderError::MissingOption(kind, "--rfc2136-server"))?;
+
+                if self.rfc2136_zones.is_empty() {
+                    return Err(NewProviderError::MissingOption(kind, "--rfc2136-zone"));
+                }
+
+                let tsig_key = TsigKey::from_base64(
+                    self.tsig_key_name.as_deref(),
+                    self.tsig_algorithm,
+                    self.tsig_secret.as_deref(),
+                )?;
+
+                Ok(ProviderConfig::Rfc2136(Rfc2136Config {
+                    server,
+                    zones: self.rfc2136_zones.clone(),
+                    tcp: self.rfc2136_tcp,
+                    tsig_key,
+                    timeout: rfc2136::DEFAULT_TIMEOUT,
+                }))
+            }
+        }
+    }
+}
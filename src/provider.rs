@@ -0,0 +1,206 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cloudflare::Cloudflare,
+    digitalocean::{DigitalOcean, QueryError},
+    rfc2136::{Rfc2136, Rfc2136Config},
+};
+
+/// The DNS providers that records can be managed through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[value(name = "digitalocean")]
+    DigitalOcean,
+    #[value(name = "cloudflare")]
+    Cloudflare,
+    /// Dynamic updates (nsupdate) sent directly to an authoritative name server
+    #[value(name = "rfc2136")]
+    Rfc2136,
+}
+
+impl std::fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderKind::DigitalOcean => write!(f, "DigitalOcean"),
+            ProviderKind::Cloudflare => write!(f, "Cloudflare"),
+            ProviderKind::Rfc2136 => write!(f, "RFC 2136"),
+        }
+    }
+}
+
+/// A DNS zone (domain) that is hosted by a provider.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Zone {
+    /// Provider specific identifier of the zone.
+    /// For providers that identify zones by name this is the same as `name`.
+    pub id: String,
+    /// The name of the zone, for example `example.com`.
+    pub name: String,
+}
+
+/// A single DNS record inside a zone.
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    /// Provider specific identifier of the record.
+    pub id: String,
+    /// The type of the DNS record. For example: A, AAAA, CNAME, ...
+    pub ty: String,
+    /// The name of the record relative to the zone, `@` for the zone apex.
+    pub name: String,
+    /// The value of the record, for example the IP address of an A record.
+    pub data: String,
+    /// The time to live of the record, in seconds.
+    pub ttl: u32,
+    /// Whether traffic to the record is proxied by the provider (Cloudflare only).
+    /// Providers that support it keep this value unchanged when updating the record.
+    pub proxied: Option<bool>,
+}
+
+impl DnsRecord {
+    /// Returns the fully qualified domain name of this record in the given zone.
+    pub fn fqdn(&self, zone: &Zone) -> String {
+        format!("{}.{}", self.name, zone.name)
+    }
+}
+
+/// Converts a fully qualified name to a name relative to the zone (`@` for the zone apex).
+/// Names that are already relative in the form of `@.zone` are also accepted.
+pub fn relative_record_name(fqdn: &str, zone_name: &str) -> String {
+    let fqdn_lowercase = fqdn.to_lowercase();
+    let zone_lowercase = zone_name.to_lowercase();
+
+    if fqdn_lowercase == zone_lowercase {
+        return "@".to_string();
+    }
+
+    match fqdn_lowercase.strip_suffix(&format!(".{zone_lowercase}")) {
+        Some(name) => name.to_string(),
+        None => fqdn_lowercase,
+    }
+}
+
+/// Converts a name relative to the zone back to a fully qualified name.
+pub fn absolute_record_name(name: &str, zone_name: &str) -> String {
+    if name == "@" {
+        zone_name.to_string()
+    } else {
+        format!("{name}.{zone_name}")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProviderError {
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("RateLimited: {0}")]
+    RateLimited(String),
+    #[error("NotFound: {0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Other(anyhow::Error),
+}
+
+impl From<QueryError> for ProviderError {
+    fn from(err: QueryError) -> Self {
+        match err {
+            QueryError::Unauthorized(message) => ProviderError::Unauthorized(message),
+            QueryError::RateLimited(message) => ProviderError::RateLimited(message),
+            QueryError::NotFound(message) => ProviderError::NotFound(message),
+            err => ProviderError::Other(err.into()),
+        }
+    }
+}
+
+/// A DNS hosting service that zones and records can be queried and modified through.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Human readable name of the provider, used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Lists all zones that are accessible with the configured credentials.
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError>;
+
+    /// Lists the records in the specified zone.
+    /// `names` are the fully qualified names the caller is interested in. Providers that can
+    /// enumerate the zone return all records, others only return the records of these names.
+    async fn list_records(
+        &self,
+        zone: &Zone,
+        names: &[&str],
+    ) -> Result<Vec<DnsRecord>, ProviderError>;
+
+    /// Sets the value and TTL of an existing record and returns the updated record.
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &DnsRecord,
+        new_value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError>;
+
+    /// Creates a new record in the zone and returns it.
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        name: &str,
+        ty: &str,
+        value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError>;
+
+    /// Deletes an existing record from the zone.
+    async fn delete_record(&self, zone: &Zone, record: &DnsRecord) -> Result<(), ProviderError>;
+}
+
+/// Everything needed to create a client for one of the providers.
+#[derive(Debug, Clone)]
+pub enum ProviderConfig {
+    DigitalOcean { api_key: String, api_base: String },
+    Cloudflare { api_token: String, api_base: String },
+    Rfc2136(Rfc2136Config),
+}
+
+impl ProviderConfig {
+    pub fn kind(&self) -> ProviderKind {
+        match self {
+            ProviderConfig::DigitalOcean { .. } => ProviderKind::DigitalOcean,
+            ProviderConfig::Cloudflare { .. } => ProviderKind::Cloudflare,
+            ProviderConfig::Rfc2136(_) => ProviderKind::Rfc2136,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NewProviderError {
+    #[error("No API key was specified for {0}")]
+    MissingApiKey(ProviderKind),
+    #[error("{1} must be specified when using {0}")]
+    MissingOption(ProviderKind, &'static str),
+    #[error("Invalid value for {1}: {2}")]
+    InvalidOption(ProviderKind, &'static str, String),
+    #[error("Failed to create {0} client: {1}")]
+    ClientError(ProviderKind, anyhow::Error),
+}
+
+/// Creates the provider client described by `config`.
+pub fn create_provider(config: ProviderConfig) -> Result<Box<dyn DnsProvider>, NewProviderError> {
+    let kind = config.kind();
+    let client_error = |err: anyhow::Error| NewProviderError::ClientError(kind, err);
+
+    Ok(match config {
+        ProviderConfig::DigitalOcean { api_key, api_base } => {
+            Box::new(DigitalOcean::new(api_key, &api_base).map_err(|err| client_error(err.into()))?)
+        }
+        ProviderConfig::Cloudflare {
+            api_token,
+            api_base,
+        } => {
+            Box::new(Cloudflare::new(api_token, &api_base).map_err(|err| client_error(err.into()))?)
+        }
+        ProviderConfig::Rfc2136(config) => {
+            Box::new(Rfc2136::new(config).map_err(|err| client_error(err.into()))?)
+        }
+    })
+}
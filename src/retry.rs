@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff with jitter for retrying failed requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How many times a failed request is retried before giving up.
+    pub max_retries: u32,
+    /// The backoff of the first retry, doubled on every following retry.
+    pub base_delay: Duration,
+    /// Upper bound of the backoff.
+    pub max_delay: Duration,
+    /// The longest a server may ask us to wait (with `Retry-After` or similar headers).
+    /// If it asks for more the request fails instead of blocking the update.
+    pub max_requested_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_requested_delay: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry (0 for the first one).
+    /// The delay is randomized between half and all of the exponential backoff, so that
+    /// clients that failed at the same time don't all retry at the same time.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        let jitter = rand::rng().random_range(0..=half.as_millis() as u64);
+
+        half + Duration::from_millis(jitter)
+    }
+}
@@ -0,0 +1,533 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use base64::Engine;
+use hickory_proto::{
+    dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+    op::{
+        update_message::{self, UpdateMessage},
+        Message, MessageType, OpCode, Query, ResponseCode,
+    },
+    rr::{rdata, Name, RData, Record, RecordType},
+    ProtoError,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+use crate::provider::{
+    absolute_record_name, relative_record_name, DnsProvider, DnsRecord, NewProviderError,
+    ProviderError, ProviderKind, Zone,
+};
+
+/// Maximum time difference (in seconds) between our clock and the server's clock
+/// that is accepted when verifying TSIG signatures.
+const TSIG_FUDGE: u16 = 300;
+const DEFAULT_PORT: u16 = 53;
+const MAX_UDP_RESPONSE_SIZE: usize = 4096;
+/// How long to wait for a response from the server.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The TSIG algorithms that can be used to sign updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithmArg {
+    #[value(name = "hmac-sha256")]
+    HmacSha256,
+    #[value(name = "hmac-sha512")]
+    HmacSha512,
+}
+
+impl From<TsigAlgorithmArg> for TsigAlgorithm {
+    fn from(algorithm: TsigAlgorithmArg) -> Self {
+        match algorithm {
+            TsigAlgorithmArg::HmacSha256 => TsigAlgorithm::HmacSha256,
+            TsigAlgorithmArg::HmacSha512 => TsigAlgorithm::HmacSha512,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TsigKey {
+    /// Name of the key, must match the name configured on the server.
+    pub name: String,
+    pub algorithm: TsigAlgorithmArg,
+    /// The decoded shared secret.
+    pub secret: Vec<u8>,
+}
+
+// Not derived to avoid printing the secret
+impl std::fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TsigKey {
+    /// Builds the key from its name and base64 encoded secret.
+    /// Returns `None` if neither are specified, and an error if only one of them is.
+    pub fn from_base64(
+        name: Option<&str>,
+        algorithm: TsigAlgorithmArg,
+        secret: Option<&str>,
+    ) -> Result<Option<Self>, NewProviderError> {
+        let kind = ProviderKind::Rfc2136;
+
+        match (name, secret) {
+            (Some(name), Some(secret)) => Ok(Some(TsigKey {
+                name: name.to_string(),
+                algorithm,
+                secret: base64::engine::general_purpose::STANDARD
+                    .decode(secret.trim())
+                    .map_err(|err| {
+                        NewProviderError::InvalidOption(kind, "tsig secret", err.to_string())
+                    })?,
+            })),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(NewProviderError::MissingOption(kind, "tsig secret")),
+            (None, Some(_)) => Err(NewProviderError::MissingOption(kind, "tsig key name")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rfc2136Config {
+    /// The authoritative server, either `host`, `host:port` or an IP address.
+    pub server: String,
+    /// The zones that the server is the primary for.
+    pub zones: Vec<String>,
+    /// Always use TCP instead of trying UDP first.
+    pub tcp: bool,
+    pub tsig_key: Option<TsigKey>,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+    #[error("Timed out waiting for a response from the server")]
+    Timeout,
+    #[error("Protocol error: {0}")]
+    Proto(ProtoError),
+    #[error("The server refused the request: {0}")]
+    Refused(ResponseCode),
+    #[error("The server responded with {0}")]
+    ErrorResponse(ResponseCode),
+    #[error("Response id {0} does not match request id {1}")]
+    MismatchedId(u16, u16),
+    #[error("Could not resolve server address: {0}")]
+    UnresolvedServer(String),
+    #[error("Invalid name: {0}")]
+    InvalidName(String),
+    #[error("{1} is not a valid {0} value")]
+    InvalidValue(RecordType, String),
+    #[error("Record type {0} is not supported by RFC 2136 updates")]
+    UnsupportedType(String),
+}
+
+impl From<std::io::Error> for QueryError {
+    fn from(err: std::io::Error) -> Self {
+        QueryError::Io(err)
+    }
+}
+
+impl From<ProtoError> for QueryError {
+    fn from(err: ProtoError) -> Self {
+        QueryError::Proto(err)
+    }
+}
+
+impl From<QueryError> for ProviderError {
+    fn from(err: QueryError) -> Self {
+        match err {
+            QueryError::Refused(code) => ProviderError::Unauthorized(code.to_string()),
+            err => ProviderError::Other(err.into()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NewClientError {
+    #[error("The TSIG key could not be used: {0}")]
+    InvalidTsigKey(String),
+    #[error("Invalid TSIG key name: {0}")]
+    InvalidKeyName(ProtoError),
+    #[error("No zones were specified")]
+    NoZones,
+}
+
+/// Updates records on an authoritative server with RFC 2136 dynamic updates.
+pub struct Rfc2136 {
+    server: String,
+    zones: Vec<String>,
+    tcp: bool,
+    signer: Option<TSigner>,
+    timeout: Duration,
+}
+
+impl Rfc2136 {
+    pub fn new(config: Rfc2136Config) -> Result<Self, NewClientError> {
+        if config.zones.is_empty() {
+            return Err(NewClientError::NoZones);
+        }
+
+        let signer = match config.tsig_key {
+            Some(key) => {
+                let key_name =
+                    Name::from_ascii(&key.name).map_err(NewClientError::InvalidKeyName)?;
+
+                Some(
+                    TSigner::new(key.secret, key.algorithm.into(), key_name, TSIG_FUDGE)
+                        .map_err(|err| NewClientError::InvalidTsigKey(err.to_string()))?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Rfc2136 {
+            server: config.server,
+            zones: config.zones,
+            tcp: config.tcp,
+            signer,
+            timeout: config.timeout,
+        })
+    }
+
+    /// Queries the server for the records of the given type at `fqdn`.
+    pub async fn query_records(
+        &self,
+        fqdn: &str,
+        ty: RecordType,
+    ) -> Result<Vec<Record>, QueryError> {
+        let name = parse_name(fqdn)?;
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(false);
+        message.add_query(Query::query(name.clone(), ty));
+
+        let response = self.exchange(message).await?;
+
+        match response.response_code() {
+            ResponseCode::NoError | ResponseCode::NXDomain => {}
+            ResponseCode::Refused | ResponseCode::NotAuth => {
+                return Err(QueryError::Refused(response.response_code()))
+            }
+            code => return Err(QueryError::ErrorResponse(code)),
+        }
+
+        Ok(response
+            .answers()
+            .iter()
+            .filter(|record| record.record_type() == ty && record.name().eq_case(&name))
+            .cloned()
+            .collect())
+    }
+
+    /// Replaces the whole `ty` RRset at `fqdn` with a single record in one atomic update.
+    pub async fn replace_rrset(
+        &self,
+        zone_name: &str,
+        fqdn: &str,
+        ty: RecordType,
+        value: &str,
+        ttl: u32,
+    ) -> Result<(), QueryError> {
+        let zone = parse_name(zone_name)?;
+        let name = parse_name(fqdn)?;
+        let rdata = parse_rdata(ty, value)?;
+
+        let mut message =
+            update_message::delete_rrset(Record::update0(name.clone(), 0, ty), zone, false);
+        message.add_update(Record::from_rdata(name, ttl, rdata));
+
+        self.send_update(message).await
+    }
+
+    /// Deletes the whole `ty` RRset at `fqdn`.
+    pub async fn delete_rrset(
+        &self,
+        zone_name: &str,
+        fqdn: &str,
+        ty: RecordType,
+    ) -> Result<(), QueryError> {
+        let zone = parse_name(zone_name)?;
+        let name = parse_name(fqdn)?;
+
+        let message = update_message::delete_rrset(Record::update0(name, 0, ty), zone, false);
+
+        self.send_update(message).await
+    }
+
+    async fn send_update(&self, message: Message) -> Result<(), QueryError> {
+        let response = self.exchange(message).await?;
+
+        match response.response_code() {
+            ResponseCode::NoError => Ok(()),
+            ResponseCode::Refused | ResponseCode::NotAuth => {
+                Err(QueryError::Refused(response.response_code()))
+            }
+            code => Err(QueryError::ErrorResponse(code)),
+        }
+    }
+
+    /// Signs the message (if a TSIG key is configured), sends it and returns the verified response.
+    async fn exchange(&self, mut message: Message) -> Result<Message, QueryError> {
+        let verifier = match &self.signer {
+            Some(signer) => message.finalize(signer, unix_time())?,
+            None => None,
+        };
+
+        let request = message.to_vec()?;
+        let server = self.resolve_server().await?;
+
+        let response = if self.tcp {
+            tokio::time::timeout(self.timeout, Self::send_tcp(server, &request))
+                .await
+                .map_err(|_| QueryError::Timeout)??
+        } else {
+            let response = tokio::time::timeout(self.timeout, Self::send_udp(server, &request))
+                .await
+                .map_err(|_| QueryError::Timeout)??;
+
+            // Retry over tcp if the response didn't fit in a datagram
+            if Message::from_vec(&response)?.truncated() {
+                tokio::time::timeout(self.timeout, Self::send_tcp(server, &request))
+                    .await
+                    .map_err(|_| QueryError::Timeout)??
+            } else {
+                response
+            }
+        };
+
+        let response = match verifier {
+            Some(mut verifier) => match verifier(&response) {
+                Ok(response) => response.into_message(),
+                Err(err) => {
+                    // Servers that can't verify our signature (BADSIG, BADKEY or BADTIME) send
+                    // back an unsigned NOTAUTH, which is only used to report the error
+                    let unsigned = Message::from_vec(&response)?;
+
+                    return match unsigned.response_code() {
+                        code @ (ResponseCode::Refused | ResponseCode::NotAuth)
+                            if unsigned.id() == message.id() =>
+                        {
+                            Err(QueryError::Refused(code))
+                        }
+                        _ => Err(err.into()),
+                    };
+                }
+            },
+            None => Message::from_vec(&response)?,
+        };
+
+        if response.id() != message.id() {
+            return Err(QueryError::MismatchedId(response.id(), message.id()));
+        }
+
+        Ok(response)
+    }
+
+    async fn send_udp(server: SocketAddr, request: &[u8]) -> Result<Vec<u8>, QueryError> {
+        let bind_address: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let socket = UdpSocket::bind(bind_address).await?;
+        socket.connect(server).await?;
+        socket.send(request).await?;
+
+        let mut buffer = vec![0; MAX_UDP_RESPONSE_SIZE];
+        let length = socket.recv(&mut buffer).await?;
+        buffer.truncate(length);
+
+        Ok(buffer)
+    }
+
+    async fn send_tcp(server: SocketAddr, request: &[u8]) -> Result<Vec<u8>, QueryError> {
+        let mut stream = TcpStream::connect(server).await?;
+
+        // Messages over tcp are prefixed with their length
+        stream.write_u16(request.len() as u16).await?;
+        stream.write_all(request).await?;
+
+        let length = stream.read_u16().await?;
+        let mut buffer = vec![0; length as usize];
+        stream.read_exact(&mut buffer).await?;
+
+        Ok(buffer)
+    }
+
+    async fn resolve_server(&self) -> Result<SocketAddr, QueryError> {
+        if let Ok(address) = self.server.parse::<SocketAddr>() {
+            return Ok(address);
+        }
+
+        if let Ok(ip) = self.server.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+        }
+
+        let host = if self.server.contains(':') {
+            self.server.clone()
+        } else {
+            format!("{}:{DEFAULT_PORT}", self.server)
+        };
+
+        let address = tokio::net::lookup_host(&host).await?.next();
+
+        address.ok_or_else(|| QueryError::UnresolvedServer(self.server.clone()))
+    }
+}
+
+fn parse_name(name: &str) -> Result<Name, QueryError> {
+    let mut name = Name::from_ascii(name).map_err(|_| QueryError::InvalidName(name.to_string()))?;
+    name.set_fqdn(true);
+
+    Ok(name)
+}
+
+fn parse_rdata(ty: RecordType, value: &str) -> Result<RData, QueryError> {
+    let invalid_value = || QueryError::InvalidValue(ty, value.to_string());
+
+    match ty {
+        RecordType::A => Ok(RData::A(rdata::A(
+            value.parse().map_err(|_| invalid_value())?,
+        ))),
+        RecordType::AAAA => Ok(RData::AAAA(rdata::AAAA(
+            value.parse().map_err(|_| invalid_value())?,
+        ))),
+        _ => Err(QueryError::UnsupportedType(ty.to_string())),
+    }
+}
+
+fn parse_record_type(ty: &str) -> Result<RecordType, QueryError> {
+    match ty {
+        "A" => Ok(RecordType::A),
+        "AAAA" => Ok(RecordType::AAAA),
+        other => Err(QueryError::UnsupportedType(other.to_string())),
+    }
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    fn name(&self) -> &'static str {
+        "RFC 2136"
+    }
+
+    /// The zones can't be listed over DNS, so the configured zones are returned as is.
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        Ok(self
+            .zones
+            .iter()
+            .map(|zone| {
+                let name = zone.trim_end_matches('.').to_lowercase();
+
+                Zone {
+                    id: name.clone(),
+                    name,
+                }
+            })
+            .collect())
+    }
+
+    async fn list_records(
+        &self,
+        zone: &Zone,
+        names: &[&str],
+    ) -> Result<Vec<DnsRecord>, ProviderError> {
+        let mut records = vec![];
+
+        for name in names {
+            let fqdn = absolute_record_name(&relative_record_name(name, &zone.name), &zone.name);
+
+            for ty in [RecordType::A, RecordType::AAAA] {
+                for record in self.query_records(&fqdn, ty).await? {
+                    let Some(ip) = record.data().ip_addr() else {
+                        continue;
+                    };
+
+                    records.push(DnsRecord {
+                        id: format!("{fqdn}/{ty}"),
+                        ty: ty.to_string(),
+                        name: relative_record_name(&fqdn, &zone.name),
+                        data: ip.to_string(),
+                        ttl: record.ttl(),
+                        proxied: None,
+                    });
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    async fn update_record(
+        &self,
+        zone: &Zone,
+        record: &DnsRecord,
+        new_value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        let fqdn = absolute_record_name(&record.name, &zone.name);
+        let ty = parse_record_type(&record.ty)?;
+
+        self.replace_rrset(&zone.name, &fqdn, ty, new_value, ttl)
+            .await?;
+
+        Ok(DnsRecord {
+            data: new_value.to_string(),
+            ttl,
+            ..record.clone()
+        })
+    }
+
+    async fn create_record(
+        &self,
+        zone: &Zone,
+        name: &str,
+        ty: &str,
+        value: &str,
+        ttl: u32,
+    ) -> Result<DnsRecord, ProviderError> {
+        let fqdn = absolute_record_name(name, &zone.name);
+        let record_type = parse_record_type(ty)?;
+
+        self.replace_rrset(&zone.name, &fqdn, record_type, value, ttl)
+            .await?;
+
+        Ok(DnsRecord {
+            id: format!("{fqdn}/{ty}"),
+            ty: ty.to_string(),
+            name: name.to_string(),
+            data: value.to_string(),
+            ttl,
+            proxied: None,
+        })
+    }
+
+    async fn delete_record(&self, zone: &Zone, record: &DnsRecord) -> Result<(), ProviderError> {
+        let fqdn = absolute_record_name(&record.name, &zone.name);
+        let ty = parse_record_type(&record.ty)?;
+
+        Ok(self.delete_rrset(&zone.name, &fqdn, ty).await?)
+    }
+}
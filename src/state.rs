@@ -0,0 +1,197 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::TtlPolicy,
+    domain_arg::DomainArg,
+    provider::{DnsRecord, ProviderKind},
+    wan_ip_query::IpFamily,
+};
+
+pub const DEFAULT_STATE_FILE: &str = "dns-updater-state.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("Failed to access state file {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Failed to parse state file {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("Failed to serialize state: {0}")]
+    Serialize(serde_json::Error),
+}
+
+/// What the updater knows about the records it manages, persisted between runs so that
+/// the provider APIs only need to be queried when the WAN IP changes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderState>,
+}
+
+/// The state of the records managed through one configured provider.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderState {
+    /// The kind of the provider when the state was saved.
+    /// The records are forgotten if the provider is changed to another kind.
+    pub kind: ProviderKind,
+    /// Keyed by the normalized domain name and then the record type.
+    #[serde(default)]
+    pub records: BTreeMap<String, BTreeMap<String, RecordState>>,
+}
+
+/// The last known value of a single A or AAAA record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordState {
+    /// The WAN IP the record was last verified or updated to.
+    pub ip: IpAddr,
+    /// Identifier of the zone that the record belongs to.
+    pub zone_id: String,
+    /// Identifier of the record, `None` if the record doesn't exist and isn't required to.
+    pub record_id: Option<String>,
+    /// The TTL of the record, `None` if it doesn't exist or the state was saved by an older version.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// Whether Cloudflare proxies the record, which means it manages the TTL itself.
+    #[serde(default)]
+    pub proxied: bool,
+    /// When the record was last verified or updated.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RecordState {
+    /// Returns true if the record has the TTL that `ttl` asks for, is proxied or doesn't exist.
+    fn has_desired_ttl(&self, ttl: &TtlPolicy) -> bool {
+        self.record_id.is_none()
+            || self.proxied
+            || ttl.is_unmanaged()
+            || self
+                .ttl
+                .is_some_and(|record_ttl| ttl.desired(record_ttl) == record_ttl)
+    }
+}
+
+impl State {
+    /// Loads the state from `path`. A missing file is the same as an empty state.
+    pub fn load(path: &Path) -> Result<State, StateError> {
+        let display_path = path.display().to_string();
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(err) => return Err(StateError::Io(display_path, err)),
+        };
+
+        serde_json::from_str(&contents).map_err(|err| StateError::Parse(display_path, err))
+    }
+
+    /// Saves the state to `path`. The file is replaced atomically so that an interrupted
+    /// write can't leave a corrupt state behind.
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        let display_path = path.display().to_string();
+        let contents = serde_json::to_string_pretty(self).map_err(StateError::Serialize)?;
+
+        let mut temp_path = PathBuf::from(path);
+        temp_path.as_mut_os_string().push(".tmp");
+
+        std::fs::write(&temp_path, contents)
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .map_err(|err| StateError::Io(display_path, err))
+    }
+
+    /// Returns the state of the provider with the given name,
+    /// discarding the previous state if the provider kind has changed.
+    pub fn provider(&mut self, name: &str, kind: ProviderKind) -> &mut ProviderState {
+        let state = self
+            .providers
+            .entry(name.to_string())
+            .or_insert_with(|| ProviderState {
+                kind,
+                records: BTreeMap::new(),
+            });
+
+        if state.kind != kind {
+            state.kind = kind;
+            state.records.clear();
+        }
+
+        state
+    }
+}
+
+impl ProviderState {
+    /// Returns true if every record of `domain` that can be updated was last verified to have
+    /// the current WAN IP (and the TTL of `ttl`) less than `max_staleness` ago.
+    pub fn is_up_to_date(
+        &self,
+        domain_name: &str,
+        domain: &DomainArg,
+        wan_ips: &HashMap<IpFamily, IpAddr>,
+        ttl: &TtlPolicy,
+        max_staleness: Duration,
+    ) -> bool {
+        let now = Utc::now();
+        let records = self.records.get(domain_name);
+        let mut checked_any = false;
+
+        for family in domain.families() {
+            let Some(wan_ip) = wan_ips.get(&family) else {
+                continue;
+            };
+
+            let record = records.and_then(|records| records.get(family.record_type()));
+
+            match record {
+                Some(record)
+                    if record.ip == *wan_ip
+                        && record.has_desired_ttl(ttl)
+                        && now - record.updated_at < max_staleness =>
+                {
+                    checked_any = true;
+                }
+                _ => return false,
+            }
+        }
+
+        checked_any
+    }
+
+    /// Returns the IP that the `family` record of a domain was last verified or updated to.
+    pub fn record_ip(&self, domain_name: &str, family: IpFamily) -> Option<IpAddr> {
+        self.records
+            .get(domain_name)?
+            .get(family.record_type())
+            .map(|record| record.ip)
+    }
+
+    /// Remembers that the `family` record of a domain has the value `ip` as of now.
+    /// `record` is `None` if the domain has no record of that family and doesn't need one.
+    pub fn set_record(
+        &mut self,
+        domain_name: &str,
+        family: IpFamily,
+        ip: IpAddr,
+        zone_id: &str,
+        record: Option<&DnsRecord>,
+    ) {
+        self.records
+            .entry(domain_name.to_string())
+            .or_default()
+            .insert(
+                family.record_type().to_string(),
+                RecordState {
+                    ip,
+                    zone_id: zone_id.to_string(),
+                    record_id: record.map(|record| record.id.clone()),
+                    ttl: record.map(|record| record.ttl),
+                    proxied: record.is_some_and(|record| record.proxied == Some(true)),
+                    updated_at: Utc::now(),
+                },
+            );
+    }
+}
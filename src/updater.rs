@@ -1,236 +1,792 @@
-use std::{collections::HashMap, net::IpAddr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    num::NonZeroUsize,
+};
 
-use anyhow::Context;
-use log::Level;
+use futures_util::future::join_all;
 
 use crate::{
-    digitalocean::{DigitalOcean, Domain, QueryError},
-    wan_ip_query::query_wan_ip,
-    AppArgs, AppError,
+    address_policy::AddressPolicy,
+    address_watch::AddressWatcher,
+    config::{RecordEntry, Settings, TtlPolicy},
+    domain_arg::DomainArg,
+    flap_damping::{FlapDamper, Verdict},
+    provider::{
+        absolute_record_name, create_provider, relative_record_name, DnsProvider, DnsRecord,
+        ProviderError, Zone,
+    },
+    retry::RetryPolicy,
+    state::{ProviderState, State},
+    wan_ip_query::{
+        query_wan_ip, query_wan_ip_quorum, IpFamily, IpSource, IpSourceSpec, WanIpError,
+    },
+    AppError,
 };
 
-pub async fn start(args: AppArgs) -> Result<(), AppError> {
-    let digital_ocean =
-        DigitalOcean::new(args.do_api_key).context("Failed to create DigitalOcean client")?;
+/// The WAN IP of each address family.
+type WanIps = HashMap<IpFamily, IpAddr>;
+
+/// A domain together with the entry it was configured in and the addresses its records should have.
+#[derive(Clone, Copy)]
+struct DomainTarget<'a> {
+    domain: &'a DomainArg,
+    entry: &'a RecordEntry,
+    wan_ips: &'a WanIps,
+}
+
+pub async fn start(settings: Settings) -> Result<(), AppError> {
+    let mut providers = BTreeMap::new();
+
+    for (name, config) in &settings.providers {
+        providers.insert(name.as_str(), create_provider(config.clone())?);
+    }
+
+    let source_chains = create_source_chains(&settings.entries);
+
+    // The state is only used when applying, a preview should always show the actual records
+    let mut state = if settings.apply {
+        match State::load(&settings.state_file) {
+            Ok(state) => Some(state),
+            Err(err) => {
+                log::warn!("{err}, starting with an empty state");
+                Some(State::default())
+            }
+        }
+    } else {
+        None
+    };
+    let max_staleness = chrono::Duration::minutes(settings.max_staleness);
+
+    // Failed updates are retried sooner than the update interval, backing off while they keep failing
+    let cycle_retry_policy = RetryPolicy {
+        base_delay: std::time::Duration::from_secs(10),
+        max_delay: std::time::Duration::from_secs(10 * 60),
+        ..RetryPolicy::default()
+    };
+    let mut failed_cycles = 0;
+
+    // Keyed by the index of the entry and the family.
+    // Only used when applying, since a preview only queries the WAN IP once
+    let mut flap_damper = (settings.apply
+        && (settings.ip_confirmations.get() > 1 || !settings.ip_confirm_duration.is_zero()))
+    .then(|| {
+        let mut flap_damper =
+            FlapDamper::new(settings.ip_confirmations, settings.ip_confirm_duration);
+
+        // Changes from the IPs in the state need confirming too, otherwise a restart during a
+        // reconnect would publish the transient IP right away
+        if let Some(state) = &state {
+            flap_damper.accept(persisted_wan_ips(&settings.entries, state));
+        }
+
+        flap_damper
+    });
+
+    // Created before the first update, so changes during the update aren't missed
+    let mut address_watcher =
+        (settings.apply && settings.watch_addresses).then(AddressWatcher::new);
 
     loop {
-        if args.apply {
+        if settings.apply {
             log::info!("Starting records update...");
         } else {
             log::info!("Starting records validation (not applying any changes)...");
         }
 
-        let wan_ip = query_wan_ip().await;
+        let (wan_ips, last_error) = query_entries_wan_ips(
+            &settings.entries,
+            &source_chains,
+            settings.ip_quorum,
+            &settings.address_policy,
+        )
+        .await;
+
+        if wan_ips.iter().all(|wan_ips| wan_ips.is_empty()) {
+            let err = last_error.expect("There should always be an error if no WAN IP was found");
+
+            match err {
+                // Retrying won't make a reserved address like a CG-NAT one public. An address in
+                // a denied range may only be handed out during a reconnect though.
+                WanIpError::Rejected(_, rejected) if !settings.apply || rejected.is_reserved() => {
+                    return Err(AppError::RejectedWanIp(rejected));
+                }
+                err if settings.apply => {
+                    log::error!("Failed to query WAN IP: {err}");
 
-        if let Err(err) = wan_ip {
-            if args.apply {
-                log::error!("Failed to query WAN IP: {err}");
-                log::info!("Retrying in 10 seconds...");
+                    retry_later(&cycle_retry_policy, &mut failed_cycles).await;
+                    continue;
+                }
+                err => return Err(AppError::TestFailedToQueryWanIp(err)),
+            }
+        }
 
-                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        if let Some(flap_damper) = &mut flap_damper {
+            let observed = wan_ips
+                .iter()
+                .enumerate()
+                .flat_map(|(index, wan_ips)| {
+                    wan_ips
+                        .iter()
+                        .map(move |(family, ip)| ((index, *family), *ip))
+                })
+                .collect();
+
+            if let Verdict::Pending {
+                seen,
+                needed,
+                remaining,
+            } = flap_damper.observe(&observed)
+            {
+                let remaining = if remaining.is_zero() {
+                    String::new()
+                } else {
+                    format!(
+                        " and has to last another {} seconds",
+                        remaining.as_secs_f64().ceil()
+                    )
+                };
+
+                log::info!(
+                    "WAN IP change seen {seen} of {needed} times{remaining}, checking again in {} seconds before updating records",
+                    settings.ip_confirm_delay.as_secs()
+                );
+
+                tokio::time::sleep(settings.ip_confirm_delay).await;
                 continue;
-            } else {
-                return Err(AppError::TestFailedToQueryWanIp(err));
             }
         }
 
-        let wan_ip = wan_ip.expect("Should never be Err at this point");
+        let mut retry_soon = false;
+        let mut state_changed = false;
 
-        let wan_ip_type = match &wan_ip {
-            IpAddr::V4(_) => "A",
-            IpAddr::V6(_) => "AAAA",
-        };
+        for (provider_name, provider) in &providers {
+            let provider_state = state.as_mut().map(|state| {
+                state.provider(provider_name, settings.providers[*provider_name].kind())
+            });
 
-        // If wan type is ipv4, make sure it's not part of cg-nat subnet
-        if let IpAddr::V4(addr) = &wan_ip {
-            let [a, b, _, _] = addr.octets();
+            let target_ips = settings
+                .entries
+                .iter()
+                .zip(&wan_ips)
+                .filter(|(entry, _)| entry.provider == *provider_name)
+                .flat_map(|(entry, wan_ips)| {
+                    entry.domains.iter().map(move |domain| {
+                        (
+                            entry,
+                            domain,
+                            domain_ips(domain, wan_ips, entry.ipv6_prefix_length),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let mut targets = target_ips
+                .iter()
+                .map(|(entry, domain, wan_ips)| DomainTarget {
+                    domain,
+                    entry,
+                    wan_ips,
+                })
+                .collect::<Vec<_>>();
+
+            if let Some(provider_state) = &provider_state {
+                targets.retain(|target| {
+                    let up_to_date = provider_state.is_up_to_date(
+                        &normalize_name(&target.domain.name),
+                        target.domain,
+                        target.wan_ips,
+                        &target.entry.ttl,
+                        max_staleness,
+                    );
+
+                    if up_to_date {
+                        log::info!(
+                            "✓ {:<30}: WAN IP unchanged since the last update, skipping",
+                            target.domain.name
+                        );
+                    }
+
+                    !up_to_date
+                });
+            }
+
+            if targets.is_empty() {
+                continue;
+            }
+
+            state_changed |= provider_state.is_some();
+
+            match update_provider(provider.as_ref(), settings.apply, &targets, provider_state).await
+            {
+                Ok(()) => {}
+                Err(err @ AppError::TestFailedKeyValidation(_)) => return Err(err),
+                Err(err) if !settings.apply => return Err(err),
+                Err(err) => {
+                    log::error!("Failed to query {} account domains: {err}", provider.name());
+                    retry_soon = true;
+                }
+            }
+        }
 
-            if a == 100 && b >= 64 && b <= 127 {
-                // This should always fatally error, since there's no point in retrying
-                return Err(AppError::CgNatWanIp(wan_ip));
+        if let Some(state) = state.as_ref().filter(|_| state_changed) {
+            if let Err(err) = state.save(&settings.state_file) {
+                log::warn!("{err}");
             }
         }
 
-        log::info!("WAN IP: {}", wan_ip);
+        if settings.apply && retry_soon {
+            retry_later(&cycle_retry_policy, &mut failed_cycles).await;
+            continue;
+        }
 
-        let account_domains = digital_ocean
-            .list_all_domains()
-            .await
-            .map_err(|err| match err {
-                QueryError::Unauthorized(_) => AppError::TestFailedDOKeyValidation,
-                err => AppError::OtherError(err.into()),
-            });
+        failed_cycles = 0;
 
-        if let Err(err) = account_domains {
-            if args.apply {
-                log::error!("Failed to query account domains: {err}");
+        if !settings.apply {
+            break;
+        }
 
-                match &err {
-                    AppError::TestFailedDOKeyValidation => {
-                        return Err(err);
-                    }
-                    _ => {
-                        log::info!("Retrying in 10 seconds...");
-                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                        continue;
-                    }
+        let interval = settings.update_interval.filter(|interval| *interval != 0);
+
+        let wait_for_interval = async {
+            match interval {
+                Some(interval) => {
+                    let wait_duration = std::time::Duration::from_secs(interval as u64 * 60);
+                    let next_update_time =
+                        chrono::Local::now() + chrono::Duration::minutes(interval);
+
+                    log::info!("Next update: {}", next_update_time);
+
+                    tokio::time::sleep(wait_duration).await;
                 }
-            } else {
-                return Err(err);
+                None => std::future::pending().await,
             }
-        }
+        };
 
-        let account_domains = account_domains.expect("Should never be Err at this point");
-
-        let (map, unknown_domains) =
-            map_domain_args_to_account_domains(&args.domains, &account_domains);
-
-        let mut domain_records_futures = HashMap::with_capacity(map.len());
-
-        for (domain, _) in &map {
-            domain_records_futures.insert(domain, digital_ocean.query_domain_records(&domain.name));
-        }
-
-        for (domain, future) in domain_records_futures {
-            match future.await {
-                Ok(records) => {
-                    for arg_domain in map
-                        .get(domain)
-                        .expect("Map should always contain the domain")
-                    {
-                        let arg_domain_lowercase = arg_domain.to_lowercase();
-                        let record = records.iter().find(|rec| {
-                            rec.ty == wan_ip_type
-                                && format!("{}.{}", rec.name.to_lowercase(), &domain.name)
-                                    == arg_domain_lowercase
-                        });
-
-                        match record {
-                            Some(record) => {
-                                if !args.apply {
-                                    log::info!(
-                                        "{:<30} -> {} (current: {:>15}, TTL: {:>5}){}",
-                                        arg_domain,
-                                        wan_ip,
-                                        record.data,
-                                        record.ttl,
-                                        if wan_ip.to_string() == record.data {
-                                            " (up to date)"
-                                        } else {
-                                            ""
-                                        },
-                                    );
-                                } else {
-                                    // Update record if it's different
-                                    if wan_ip.to_string() == record.data {
-                                        let fqdn = format!("{}.{}", record.name, domain.name);
-                                        log::info!("✓ {:<30}: up to date", fqdn,);
-
-                                        continue;
-                                    }
-
-                                    match digital_ocean
-                                        .update_record(
-                                            &domain.name,
-                                            record.id,
-                                            &record.ty,
-                                            &wan_ip.to_string(),
-                                        )
-                                        .await
-                                    {
-                                        Ok(new_record) => {
-                                            let fqdn =
-                                                format!("{}.{}", new_record.name, domain.name);
-
-                                            log::info!(
-                                                "✓ {:<30} -> {} (current: {:>15}, TTL: {:>5})",
-                                                fqdn,
-                                                new_record.data,
-                                                record.data,
-                                                record.ttl,
-                                            )
-                                        }
-                                        Err(err) => {
-                                            log::error!("✗ {arg_domain:<30}: {err}",);
-                                        }
-                                    }
-                                }
-                            }
-                            None => {
-                                log::error!(
-                                    "{}{:<32}: Record does not exist, or is not of type {}",
-                                    if args.apply { "✗ " } else { "" },
-                                    arg_domain,
-                                    wan_ip_type,
-                                );
-                            }
-                        }
-                    }
+        match (&mut address_watcher, interval) {
+            (None, None) => break,
+            (None, Some(_)) => wait_for_interval.await,
+            (Some(address_watcher), _) => {
+                if interval.is_none() {
+                    log::info!("Waiting for the network addresses to change...");
                 }
-                Err(err) => {
-                    for domain in map
-                        .get(domain)
-                        .expect("Map should always contain the domain")
-                    {
-                        log::error!("{domain:<32}: {err:#?}");
+
+                tokio::select! {
+                    _ = wait_for_interval => {}
+                    _ = address_watcher.changed() => {
+                        log::info!("The network addresses changed, updating now");
                     }
                 }
             }
         }
+    }
+
+    Ok(())
+}
+
+/// Returns the addresses that the records of `domain` should have. These are the WAN IPs,
+/// unless the domain has an interface identifier, which replaces the host part of the WAN IPv6.
+fn domain_ips(domain: &DomainArg, wan_ips: &WanIps, ipv6_prefix_length: u8) -> WanIps {
+    let mut ips = wan_ips.clone();
+
+    if let (Some(interface_id), Some(IpAddr::V6(wan_ip))) =
+        (domain.interface_id, wan_ips.get(&IpFamily::V6))
+    {
+        ips.insert(
+            IpFamily::V6,
+            interface_id.apply(*wan_ip, ipv6_prefix_length).into(),
+        );
+    }
+
+    ips
+}
+
+/// Waits before retrying a failed update, backing off exponentially with the number of failures.
+async fn retry_later(retry_policy: &RetryPolicy, failed_cycles: &mut u32) {
+    let delay = retry_policy.backoff(*failed_cycles);
+    *failed_cycles += 1;
 
-        for arg_domain in unknown_domains {
-            log::error!(
-                "{}{:<32}: Domain does not exist on this DigitalOcean account",
-                if args.apply { "✗ " } else { "" },
-                arg_domain,
-            )
+    log::info!("Retrying in {} seconds...", delay.as_secs());
+    tokio::time::sleep(delay).await;
+}
+
+/// The WAN IP sources of each distinct list of source specs, in the order they're first used.
+type SourceChains<'a> = Vec<(&'a [IpSourceSpec], Vec<Box<dyn IpSource>>)>;
+
+/// Creates the WAN IP sources of the entries. Entries with the same sources share them.
+fn create_source_chains(entries: &[RecordEntry]) -> SourceChains<'_> {
+    let mut chains: SourceChains = vec![];
+
+    for entry in entries {
+        let specs = entry.ip_sources.as_slice();
+
+        if !chains.iter().any(|(chain_specs, _)| *chain_specs == specs) {
+            chains.push((specs, specs.iter().map(IpSourceSpec::create).collect()));
         }
+    }
+
+    chains
+}
+
+/// The WAN IPs that the records of each entry were last set to, keyed like the flap damper.
+/// The AAAA records of domains with an interface identifier don't have the WAN IPv6 address.
+fn persisted_wan_ips(entries: &[RecordEntry], state: &State) -> HashMap<(usize, IpFamily), IpAddr> {
+    let mut wan_ips = HashMap::new();
 
-        if args.apply {
-            if let Some(interval) = args.update_interval {
-                if interval == 0 {
-                    break;
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(provider_state) = state.providers.get(&entry.provider) else {
+            continue;
+        };
+
+        for domain in entry
+            .domains
+            .iter()
+            .filter(|domain| domain.interface_id.is_none())
+        {
+            for family in domain.families() {
+                if let Some(ip) = provider_state.record_ip(&normalize_name(&domain.name), family) {
+                    wan_ips.entry((index, family)).or_insert(ip);
                 }
+            }
+        }
+    }
 
-                let wait_duration = std::time::Duration::from_secs(interval as u64 * 60);
-                let next_update_time = chrono::Local::now() + chrono::Duration::minutes(interval);
+    wan_ips
+}
 
-                log::info!("Next update: {}", next_update_time);
+/// Queries the WAN IPs of every entry. Entries that use the same sources share the result.
+/// With a quorum, every source is queried and the IP has to be agreed on by `quorum` of them.
+/// Returns the WAN IPs in the same order as the entries, and the last error that occurred.
+async fn query_entries_wan_ips(
+    entries: &[RecordEntry],
+    chains: &SourceChains<'_>,
+    quorum: Option<NonZeroUsize>,
+    policy: &AddressPolicy,
+) -> (Vec<WanIps>, Option<WanIpError>) {
+    let mut chain_wan_ips = vec![];
+    let mut last_error = None;
+
+    for (specs, sources) in chains {
+        let chain_domains = entries
+            .iter()
+            .filter(|entry| entry.ip_sources == *specs)
+            .flat_map(|entry| entry.domains.iter());
+        let mut wan_ips = WanIps::new();
+
+        for family in wanted_families(chain_domains) {
+            let result = match quorum {
+                Some(quorum) => query_wan_ip_quorum(sources, family, quorum.get(), policy).await,
+                None => query_wan_ip(sources, family, policy).await,
+            };
+
+            match result {
+                Ok((ip, source_name)) => {
+                    log::info!("WAN {family}: {ip} (from {source_name})");
+                    wan_ips.insert(family, ip);
+                }
+                Err(err) => {
+                    log::warn!("Failed to query WAN {family}: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        chain_wan_ips.push(wan_ips);
+    }
+
+    let wan_ips = entries
+        .iter()
+        .map(|entry| {
+            let index = chains
+                .iter()
+                .position(|(specs, _)| entry.ip_sources == *specs)
+                .expect("Every entry should have a source chain");
 
-                tokio::time::sleep(wait_duration).await;
-            } else {
-                break;
+            chain_wan_ips[index].clone()
+        })
+        .collect();
+
+    (wan_ips, last_error)
+}
+
+/// Updates the records of every target that is hosted by `provider`.
+/// The records that have the WAN IP afterwards are remembered in `state`, if given.
+async fn update_provider(
+    provider: &dyn DnsProvider,
+    apply: bool,
+    targets: &[DomainTarget<'_>],
+    mut state: Option<&mut ProviderState>,
+) -> Result<(), AppError> {
+    let account_domains = provider.list_zones().await.map_err(|err| match err {
+        ProviderError::Unauthorized(_) => AppError::TestFailedKeyValidation(provider.name()),
+        err => AppError::OtherError(err.into()),
+    })?;
+
+    let (map, unknown_domains, ambiguous_domains) =
+        map_domain_args_to_account_domains(targets, &account_domains);
+
+    let domain_records = join_all(map.iter().map(|(domain, domain_targets)| async move {
+        let names = domain_targets
+            .iter()
+            .map(|target| target.domain.name.as_str())
+            .collect::<Vec<_>>();
+
+        (
+            *domain,
+            domain_targets,
+            provider.list_records(domain, &names).await,
+        )
+    }))
+    .await;
+
+    for (domain, domain_targets, records) in domain_records {
+        match records {
+            Ok(records) => {
+                for target in domain_targets {
+                    let synced = update_domain(
+                        provider,
+                        apply,
+                        target.entry,
+                        domain,
+                        &records,
+                        target.domain,
+                        target.wan_ips,
+                    )
+                    .await;
+
+                    if let Some(state) = state.as_deref_mut() {
+                        let domain_name = normalize_name(&target.domain.name);
+
+                        for (family, record) in synced {
+                            state.set_record(
+                                &domain_name,
+                                family,
+                                target.wan_ips[&family],
+                                &domain.id,
+                                record.as_ref(),
+                            );
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                for target in domain_targets {
+                    log::error!("{:<32}: {err:#?}", target.domain.name);
+                }
             }
-        } else {
-            break;
         }
     }
 
+    for target in unknown_domains {
+        log::error!(
+            "{}{:<32}: Domain does not exist on this {} account",
+            if apply { "✗ " } else { "" },
+            target.domain.name,
+            provider.name(),
+        )
+    }
+
+    for (target, zones) in ambiguous_domains {
+        log::error!(
+            "{}{:<32}: Domain matches multiple zones named {} on this {} account (ids: {})",
+            if apply { "✗ " } else { "" },
+            target.domain.name,
+            zones[0].name,
+            provider.name(),
+            zones
+                .iter()
+                .map(|zone| zone.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+
     Ok(())
 }
 
+/// Returns the address families that at least one of the domains wants to update.
+fn wanted_families<'a>(domains: impl Iterator<Item = &'a DomainArg> + Clone) -> Vec<IpFamily> {
+    IpFamily::ALL
+        .into_iter()
+        .filter(|family| {
+            domains
+                .clone()
+                .any(|domain| domain.families().contains(family))
+        })
+        .collect()
+}
+
+/// Updates (or previews the update of) the A and/or AAAA records of a single domain.
+/// If the `create_ttl` of the entry is set, records that don't exist are created with that TTL.
+///
+/// Returns the families whose records are known to have the WAN IP afterwards, with the record.
+/// The record is `None` if the domain has no record of that family and doesn't need one.
+async fn update_domain(
+    provider: &dyn DnsProvider,
+    apply: bool,
+    entry: &RecordEntry,
+    domain: &Zone,
+    records: &[DnsRecord],
+    arg_domain: &DomainArg,
+    wan_ips: &WanIps,
+) -> Vec<(IpFamily, Option<DnsRecord>)> {
+    let record_name = relative_record_name(&normalize_name(&arg_domain.name), &domain.name);
+    let mut found_record = false;
+    let mut missing_families = vec![];
+    let mut synced = vec![];
+
+    for family in arg_domain.families() {
+        let wan_ip_type = family.record_type();
+
+        let Some(wan_ip) = wan_ips.get(&family) else {
+            // Only complain if the user explicitly asked for this family
+            if arg_domain.family.is_some() {
+                log::error!(
+                    "{}{:<32}: WAN {family} address is unknown, not updating {wan_ip_type} record",
+                    if apply { "✗ " } else { "" },
+                    arg_domain.name,
+                );
+            }
+
+            continue;
+        };
+
+        let record = records
+            .iter()
+            .find(|rec| rec.ty == wan_ip_type && rec.name.to_lowercase() == record_name);
+
+        let synced_record = match record {
+            Some(record) => {
+                found_record = true;
+                update_record(
+                    provider,
+                    apply,
+                    domain,
+                    record,
+                    &arg_domain.name,
+                    wan_ip,
+                    &entry.ttl,
+                )
+                .await
+            }
+            None => match entry.create_ttl {
+                Some(ttl) => {
+                    create_record(provider, apply, domain, &record_name, family, wan_ip, ttl).await
+                }
+                None => {
+                    missing_families.push(family);
+                    continue;
+                }
+            },
+        };
+
+        if let Some(synced_record) = synced_record {
+            synced.push((family, Some(synced_record)));
+        }
+    }
+
+    // A domain without an explicit record type is allowed to only have one of the record types
+    if !missing_families.is_empty() && (arg_domain.family.is_some() || !found_record) {
+        log::error!(
+            "{}{:<32}: Record does not exist, or is not of type {}",
+            if apply { "✗ " } else { "" },
+            arg_domain.name,
+            missing_families
+                .iter()
+                .map(|family| family.record_type())
+                .collect::<Vec<_>>()
+                .join("/"),
+        );
+    } else {
+        for family in missing_families {
+            log::debug!(
+                "{:<32}: No {} record, skipping",
+                arg_domain.name,
+                family.record_type()
+            );
+
+            synced.push((family, None));
+        }
+    }
+
+    synced
+}
+
+/// Creates (or previews the creation of) a record. Returns the created record.
+async fn create_record(
+    provider: &dyn DnsProvider,
+    apply: bool,
+    domain: &Zone,
+    record_name: &str,
+    family: IpFamily,
+    wan_ip: &IpAddr,
+    ttl: u32,
+) -> Option<DnsRecord> {
+    let fqdn = absolute_record_name(record_name, &domain.name);
+    let ty = family.record_type();
+
+    if !apply {
+        log::info!("{fqdn:<30} -> {wan_ip} (new {ty} record, TTL: {ttl:>5})");
+        return None;
+    }
+
+    match provider
+        .create_record(domain, record_name, ty, &wan_ip.to_string(), ttl)
+        .await
+    {
+        Ok(new_record) => {
+            log::info!(
+                "✓ {:<30} -> {} (created {} record, TTL: {:>5})",
+                fqdn,
+                new_record.data,
+                new_record.ty,
+                new_record.ttl,
+            );
+
+            Some(new_record)
+        }
+        Err(err) => {
+            log::error!("✗ {fqdn:<30}: Failed to create {ty} record: {err}");
+            None
+        }
+    }
+}
+
+/// Updates (or previews the update of) a record to the WAN IP and the TTL of the policy.
+/// Returns the record if it has both afterwards.
+async fn update_record(
+    provider: &dyn DnsProvider,
+    apply: bool,
+    domain: &Zone,
+    record: &DnsRecord,
+    arg_domain: &str,
+    wan_ip: &IpAddr,
+    ttl: &TtlPolicy,
+) -> Option<DnsRecord> {
+    // Cloudflare manages the TTL of proxied records itself
+    let desired_ttl = if record.proxied == Some(true) {
+        record.ttl
+    } else {
+        ttl.desired(record.ttl)
+    };
+    let ttl_change = if desired_ttl == record.ttl {
+        format!("{:>5}", record.ttl)
+    } else {
+        format!("{:>5} -> {desired_ttl}", record.ttl)
+    };
+    let up_to_date = wan_ip.to_string() == record.data && desired_ttl == record.ttl;
+
+    if !apply {
+        log::info!(
+            "{:<30} -> {} (current: {:>15}, TTL: {ttl_change}){}",
+            arg_domain,
+            wan_ip,
+            record.data,
+            if up_to_date { " (up to date)" } else { "" },
+        );
+
+        return None;
+    }
+
+    // Update record if it's different
+    if up_to_date {
+        log::info!("✓ {:<30}: up to date", record.fqdn(domain));
+        return Some(record.clone());
+    }
+
+    match provider
+        .update_record(domain, record, &wan_ip.to_string(), desired_ttl)
+        .await
+    {
+        Ok(new_record) => {
+            log::info!(
+                "✓ {:<30} -> {} (current: {:>15}, TTL: {ttl_change})",
+                new_record.fqdn(domain),
+                new_record.data,
+                record.data,
+            );
+
+            Some(new_record)
+        }
+        Err(err) => {
+            log::error!("✗ {arg_domain:<30}: {err}",);
+            None
+        }
+    }
+}
+
+/// The result of looking up the zone that a domain belongs to.
+enum ZoneMatch<'a> {
+    Found(&'a Zone),
+    Unknown,
+    /// Several zones with the same name matched equally well.
+    Ambiguous(Vec<&'a Zone>),
+}
+
+/// Normalizes a domain name for comparison: lowercase and without a trailing dot.
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Finds the most specific zone that `domain_name` belongs to.
+/// Zones only match on label boundaries, so `foo.notexample.com` does not belong to `example.com`,
+/// and `host.lab.example.com` belongs to `lab.example.com` rather than `example.com` if both exist.
+fn find_zone<'a>(domain_name: &str, account_domains: &'a [Zone]) -> ZoneMatch<'a> {
+    let domain_name = normalize_name(domain_name);
+    let mut best_matches: Vec<&Zone> = vec![];
+    let mut best_length = 0;
+
+    for zone in account_domains {
+        let zone_name = normalize_name(&zone.name);
+
+        let is_match = domain_name == zone_name
+            || domain_name
+                .strip_suffix(&zone_name)
+                .is_some_and(|prefix| prefix.ends_with('.'));
+
+        if !is_match || zone_name.len() < best_length {
+            continue;
+        }
+
+        if zone_name.len() > best_length {
+            best_matches.clear();
+            best_length = zone_name.len();
+        }
+
+        best_matches.push(zone);
+    }
+
+    match best_matches.len() {
+        0 => ZoneMatch::Unknown,
+        1 => ZoneMatch::Found(best_matches[0]),
+        _ => ZoneMatch::Ambiguous(best_matches),
+    }
+}
+
+type DomainMapping<'a, 'b> = (
+    HashMap<&'b Zone, Vec<DomainTarget<'a>>>,
+    Vec<DomainTarget<'a>>,
+    Vec<(DomainTarget<'a>, Vec<&'b Zone>)>,
+);
+
+/// Groups the domain arguments by the zone they belong to.
+/// Also returns the domains that don't belong to any zone, and the ones that belong to multiple
+/// zones with the same name.
 fn map_domain_args_to_account_domains<'a, 'b>(
-    domain_args: &'a [String],
-    account_domains: &'b [Domain],
-) -> (HashMap<&'b Domain, Vec<&'a str>>, Vec<&'a str>) {
+    targets: &[DomainTarget<'a>],
+    account_domains: &'b [Zone],
+) -> DomainMapping<'a, 'b> {
     let mut map = HashMap::new();
     let mut unknown_domains = vec![];
+    let mut ambiguous_domains = vec![];
 
-    for domain_arg in domain_args {
-        let domain = account_domains.iter().find(|domain| {
-            domain_arg
-                .to_lowercase()
-                .ends_with(&domain.name.to_lowercase())
-        });
-
-        if let Some(domain) = domain {
-            map.entry(domain)
-                .or_insert(vec![])
-                .push(domain_arg.as_str());
-        } else {
-            unknown_domains.push(domain_arg.as_str());
+    for target in targets {
+        match find_zone(&target.domain.name, account_domains) {
+            ZoneMatch::Found(domain) => map.entry(domain).or_insert(vec![]).push(*target),
+            ZoneMatch::Unknown => unknown_domains.push(*target),
+            ZoneMatch::Ambiguous(zones) => ambiguous_domains.push((*target, zones)),
         }
     }
 
-    (map, unknown_domains)
+    (map, unknown_domains, ambiguous_domains)
 }
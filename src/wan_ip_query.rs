@@ -1,11 +1,35 @@
-use std::net::IpAddr;
+mod command;
+mod dns;
+mod http;
+mod interface;
+mod natpmp;
+mod stun;
+mod udp;
+mod upnp;
 
-use reqwest::Url;
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
 };
 
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::address_policy::{AddressPolicy, RejectedAddress};
+
+pub use command::{CommandSource, CommandSpec, ParseCommandError};
+pub use dns::{DnsLookup, DnsSource};
+pub use http::{Extract, HttpEndpoint, HttpSource, ParseEndpointError, Pattern};
+pub use interface::InterfaceSource;
+pub use natpmp::{NatPmpSource, PcpSource};
+pub use stun::StunSource;
+pub use upnp::UpnpSource;
+
 #[derive(Debug, thiserror::Error)]
 pub enum WanIpError {
     #[error("IO error: {0}")]
@@ -16,6 +40,14 @@ pub enum WanIpError {
     UrlParse(url::ParseError),
     #[error("There are no WAN IP API endpoints configured")]
     NoApiEndpointsConfigured,
+    #[error("Invalid WAN IP endpoint \"{0}\": {1}")]
+    InvalidEndpoint(String, ParseEndpointError),
+    #[error("There are no WAN IP sources configured")]
+    NoSourcesConfigured,
+    #[error("No WAN IP was reported by at least {0} sources: {1}")]
+    NoQuorum(usize, String),
+    #[error("{0} returned an address that can't be published: {1}")]
+    Rejected(String, RejectedAddress),
 }
 
 impl From<tokio::io::Error> for WanIpError {
@@ -30,67 +62,355 @@ impl From<url::ParseError> for WanIpError {
     }
 }
 
-const DEFAULT_APIS: [&str; 2] = ["https://api.seeip.org", "https://api64.ipify.org"];
-const FILE_PATH: &str = "api_urls.txt";
+/// The address family of an IP address, and by extension the DNS record type that holds it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub const ALL: [IpFamily; 2] = [IpFamily::V4, IpFamily::V6];
+
+    /// The DNS record type that holds addresses of this family.
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            IpFamily::V4 => "A",
+            IpFamily::V6 => "AAAA",
+        }
+    }
+
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+
+    fn unspecified_address(&self) -> IpAddr {
+        match self {
+            IpFamily::V4 => Ipv4Addr::UNSPECIFIED.into(),
+            IpFamily::V6 => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// A way of finding out the WAN IP.
+#[async_trait]
+pub trait IpSource: Send + Sync {
+    /// Describes the source in log messages, for example `http` or `interface:eth0`.
+    fn name(&self) -> String;
+
+    /// Queries the WAN IP of the given address family.
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError>;
+
+    /// Queries each independent endpoint of the source (like every url of an http source)
+    /// separately, so that they count as separate votes in quorum mode.
+    /// Returns the name and result of each endpoint.
+    async fn query_each(&self, family: IpFamily) -> Vec<(String, Result<IpAddr, WanIpError>)> {
+        vec![(self.name(), self.query(family).await)]
+    }
+}
+
+/// A WAN IP source as specified on the command line or in the config file,
+/// in the form `<kind>[:<argument>]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum IpSourceSpec {
+    /// `http` queries the endpoints in api_urls.txt, `http:<endpoint>` queries the given endpoint.
+    Http(Option<Vec<HttpEndpoint>>),
+    /// `interface:<name>` reads a public address of a local network interface.
+    Interface(String),
+    /// `upnp` asks the router discovered with SSDP, `upnp:<url>` asks the router with the given
+    /// device description url.
+    Upnp(Option<Url>),
+    /// `natpmp` asks the default gateway with NAT-PMP, `natpmp:<address>` asks the given server.
+    NatPmp(Option<SocketAddr>),
+    /// `pcp` asks the default gateway with PCP, `pcp:<address>` asks the given server.
+    Pcp(Option<SocketAddr>),
+    /// `stun` asks public STUN servers, `stun:<host>[:<port>]` asks the given server.
+    Stun(Option<Vec<String>>),
+    /// `dns` looks up the name that OpenDNS or Google answer with our address,
+    /// `dns:[txt:]<name>@<server>` looks up the given name on the given server.
+    Dns(Option<Vec<DnsLookup>>),
+    /// `command:[timeout=<seconds>] <command line>` runs the command with the shell and reads
+    /// the IP from its output.
+    Command(CommandSpec),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseIpSourceError {
+    #[error("Unknown IP source \"{0}\", expected http, interface, upnp, natpmp, pcp, stun, dns or command")]
+    UnknownKind(String),
+    #[error("The {0} IP source requires an argument, as {0}:<{1}>")]
+    MissingArgument(&'static str, &'static str),
+    #[error("Invalid argument for the {0} IP source: {1}")]
+    InvalidArgument(&'static str, String),
+}
+
+impl FromStr for IpSourceSpec {
+    type Err = ParseIpSourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, argument) = match s.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (s, None),
+        };
+
+        match kind.to_lowercase().as_str() {
+            "http" => match argument {
+                Some(endpoint) => endpoint
+                    .parse::<HttpEndpoint>()
+                    .map(|endpoint| IpSourceSpec::Http(Some(vec![endpoint])))
+                    .map_err(|err| ParseIpSourceError::InvalidArgument("http", err.to_string())),
+                None => Ok(IpSourceSpec::Http(None)),
+            },
+            "interface" => match argument {
+                Some(interface) if !interface.is_empty() => {
+                    Ok(IpSourceSpec::Interface(interface.to_string()))
+                }
+                _ => Err(ParseIpSourceError::MissingArgument("interface", "name")),
+            },
+            "upnp" => match argument {
+                Some(url) => Url::parse(url)
+                    .map(|url| IpSourceSpec::Upnp(Some(url)))
+                    .map_err(|err| ParseIpSourceError::InvalidArgument("upnp", err.to_string())),
+                None => Ok(IpSourceSpec::Upnp(None)),
+            },
+            "natpmp" => match argument {
+                Some(server) => natpmp::parse_server(server)
+                    .map(|server| IpSourceSpec::NatPmp(Some(server)))
+                    .map_err(|err| ParseIpSourceError::InvalidArgument("natpmp", err.to_string())),
+                None => Ok(IpSourceSpec::NatPmp(None)),
+            },
+            "pcp" => match argument {
+                Some(server) => natpmp::parse_server(server)
+                    .map(|server| IpSourceSpec::Pcp(Some(server)))
+                    .map_err(|err| ParseIpSourceError::InvalidArgument("pcp", err.to_string())),
+                None => Ok(IpSourceSpec::Pcp(None)),
+            },
+            "stun" => match argument {
+                Some("") => Err(ParseIpSourceError::MissingArgument("stun", "host")),
+                Some(server) => Ok(IpSourceSpec::Stun(Some(vec![udp::with_default_port(
+                    server,
+                    stun::PORT,
+                )]))),
+                None => Ok(IpSourceSpec::Stun(None)),
+            },
+            "dns" => match argument {
+                Some(lookup) => lookup
+                    .parse::<DnsLookup>()
+                    .map(|lookup| IpSourceSpec::Dns(Some(vec![lookup])))
+                    .map_err(|err| ParseIpSourceError::InvalidArgument("dns", err.to_string())),
+                None => Ok(IpSourceSpec::Dns(None)),
+            },
+            "command" => match argument.map(str::parse::<CommandSpec>) {
+                Some(Ok(command)) => Ok(IpSourceSpec::Command(command)),
+                Some(Err(ParseCommandError::Empty)) | None => Err(
+                    ParseIpSourceError::MissingArgument("command", "command line"),
+                ),
+                Some(Err(err)) => Err(ParseIpSourceError::InvalidArgument(
+                    "command",
+                    err.to_string(),
+                )),
+            },
+            _ => Err(ParseIpSourceError::UnknownKind(kind.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for IpSourceSpec {
+    type Error = ParseIpSourceError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl IpSourceSpec {
+    /// Creates the source described by this spec.
+    pub fn create(&self) -> Box<dyn IpSource> {
+        match self {
+            IpSourceSpec::Http(endpoints) => Box::new(HttpSource::new(endpoints.clone())),
+            IpSourceSpec::Interface(interface) => Box::new(InterfaceSource::new(interface.clone())),
+            IpSourceSpec::Upnp(description_url) => {
+                Box::new(UpnpSource::new(description_url.clone()))
+            }
+            IpSourceSpec::NatPmp(server) => Box::new(NatPmpSource::new(*server)),
+            IpSourceSpec::Pcp(server) => Box::new(PcpSource::new(*server)),
+            IpSourceSpec::Stun(servers) => Box::new(StunSource::new(servers.clone())),
+            IpSourceSpec::Dns(lookups) => Box::new(DnsSource::new(lookups.clone())),
+            IpSourceSpec::Command(command) => {
+                Box::new(CommandSource::new(command.command.clone(), command.timeout))
+            }
+        }
+    }
+
+    /// How many votes the source has in quorum mode, one for each of its distinct endpoints.
+    /// Returns `None` if that depends on the contents of api_urls.txt.
+    pub fn votes(&self) -> Option<usize> {
+        match self {
+            // Endpoints are told apart by their url, like in query_wan_ip_quorum
+            IpSourceSpec::Http(Some(endpoints)) => Some(count_distinct(
+                endpoints.iter().map(|endpoint| &endpoint.url),
+            )),
+            IpSourceSpec::Http(None) => None,
+            IpSourceSpec::Stun(servers) => Some(
+                servers
+                    .as_ref()
+                    .map_or(stun::DEFAULT_SERVERS.len(), |servers| {
+                        count_distinct(servers.iter())
+                    }),
+            ),
+            IpSourceSpec::Dns(lookups) => Some(lookups.as_ref().map_or_else(
+                || dns::default_lookups().len(),
+                |lookups| count_distinct(lookups.iter()),
+            )),
+            _ => Some(1),
+        }
+    }
+}
+
+/// Parses the `timeout=<seconds>` option of a source, which has to be positive.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds > 0.0 && seconds.is_finite())
+        .map(Duration::from_secs_f64)
+}
 
-pub async fn query_wan_ip() -> Result<IpAddr, WanIpError> {
-    let api_urls = load_api_urls().await?;
-    let mut last_error: Option<anyhow::Error> = None;
+fn count_distinct<T: PartialEq>(items: impl Iterator<Item = T>) -> usize {
+    let mut distinct = vec![];
 
-    if api_urls.is_empty() {
-        return Err(WanIpError::NoApiEndpointsConfigured);
+    for item in items {
+        if !distinct.contains(&item) {
+            distinct.push(item);
+        }
     }
 
-    for api_url in api_urls {
-        let response = reqwest::get(api_url).await;
+    distinct.len()
+}
 
-        match response {
-            Ok(response) => match response.text().await {
-                Ok(text) => match text.parse::<IpAddr>() {
-                    Ok(ip) => return Ok(ip),
-                    Err(err) => last_error = Some(err.into()),
-                },
-                Err(err) => last_error = Some(err.into()),
+/// Queries the WAN IP of the given address family from each source in order,
+/// returning the first address that is found and the name of the source that found it.
+/// Addresses that `policy` rejects count as a failure of the source.
+pub async fn query_wan_ip(
+    sources: &[Box<dyn IpSource>],
+    family: IpFamily,
+    policy: &AddressPolicy,
+) -> Result<(IpAddr, String), WanIpError> {
+    let mut last_error = WanIpError::NoSourcesConfigured;
+
+    for source in sources {
+        match source.query(family).await {
+            Ok(ip) if IpFamily::of(&ip) == family => match policy.check(ip) {
+                Ok(()) => return Ok((ip, source.name())),
+                Err(rejected) => {
+                    log::warn!("{} returned {rejected}", source.name());
+                    last_error = WanIpError::Rejected(source.name(), rejected);
+                }
             },
-            Err(err) => last_error = Some(err.into()),
+            Ok(ip) => {
+                last_error = WanIpError::QueryFailed(anyhow::anyhow!(
+                    "{} returned an {} address ({ip}) when asked for an {family} address",
+                    source.name(),
+                    IpFamily::of(&ip),
+                ))
+            }
+            Err(err) => {
+                log::debug!("Failed to query WAN {family} from {}: {err}", source.name());
+                last_error = err;
+            }
         }
     }
 
-    Err(WanIpError::QueryFailed(last_error.unwrap_or_else(|| {
-        anyhow::anyhow!("Failed to query WAN IP")
-    })))
+    Err(last_error)
 }
 
-async fn load_api_urls() -> Result<Vec<Url>, WanIpError> {
-    let file = File::open(FILE_PATH).await;
+/// Queries every endpoint of every source concurrently, and returns the address that at least
+/// `quorum` of them agree on, with a description of the sources that reported it.
+/// Endpoints that report a different address are logged, and if more than one address
+/// reaches the quorum none of them is trusted. Addresses that `policy` rejects don't count as votes.
+pub async fn query_wan_ip_quorum(
+    sources: &[Box<dyn IpSource>],
+    family: IpFamily,
+    quorum: usize,
+    policy: &AddressPolicy,
+) -> Result<(IpAddr, String), WanIpError> {
+    let results = join_all(sources.iter().map(|source| source.query_each(family)))
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut votes = BTreeMap::<IpAddr, Vec<String>>::new();
+    let mut failed = 0;
+    let mut voted = HashSet::new();
 
-    let api_urls = match file {
-        Ok(mut file) => {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).await?;
-            contents
-                .split('\n')
-                .filter(|url| !url.is_empty())
-                .map(Url::parse)
-                .collect::<Result<Vec<_>, _>>()?
+    for (name, result) in results {
+        // An endpoint that is listed twice still only gets one vote
+        if !voted.insert(name.clone()) {
+            continue;
         }
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                let mut file = File::create(FILE_PATH).await?;
 
-                for api_url in DEFAULT_APIS {
-                    file.write_all(format!("{}\n", api_url).as_bytes()).await?;
+        match result {
+            Ok(ip) if IpFamily::of(&ip) == family => match policy.check(ip) {
+                Ok(()) => votes.entry(ip).or_default().push(name),
+                Err(rejected) => {
+                    log::warn!("{name} returned {rejected}");
+                    failed += 1;
                 }
+            },
+            Ok(ip) => {
+                log::debug!("{name} returned an {} address ({ip})", IpFamily::of(&ip));
+                failed += 1;
+            }
+            Err(err) => {
+                log::debug!("Failed to query WAN {family} from {name}: {err}");
+                failed += 1;
+            }
+        }
+    }
 
-                DEFAULT_APIS
-                    .into_iter()
-                    .map(Url::parse)
-                    .collect::<Result<Vec<_>, _>>()?
-            } else {
-                return Err(WanIpError::Io(err));
+    let total = votes.values().map(Vec::len).sum::<usize>() + failed;
+    let mut agreed = votes.iter().filter(|(_, names)| names.len() >= quorum);
+
+    match (agreed.next(), agreed.next()) {
+        (Some((ip, names)), None) => {
+            for (other_ip, other_names) in votes.iter().filter(|(other_ip, _)| *other_ip != ip) {
+                for name in other_names {
+                    log::warn!(
+                        "{name} reported WAN {family} {other_ip}, but the quorum agrees on {ip}"
+                    );
+                }
             }
+
+            Ok((
+                *ip,
+                format!("{}/{total} sources: {}", names.len(), names.join(", ")),
+            ))
         }
-    };
+        _ => {
+            let mut summary = votes
+                .iter()
+                .map(|(ip, names)| format!("{ip} from {}", names.join(", ")))
+                .collect::<Vec<_>>();
+
+            if failed > 0 {
+                summary.push(format!("{failed} failed"));
+            }
 
-    Ok(api_urls)
+            Err(WanIpError::NoQuorum(quorum, summary.join("; ")))
+        }
+    }
 }
@@ -0,0 +1,152 @@
+use std::{net::IpAddr, process::Stdio, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use tokio::process::Command;
+
+use super::{parse_timeout, IpFamily, IpSource, WanIpError};
+
+/// How long a command may run before it's killed.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Tells the command which family is queried, as `4` or `6`.
+const FAMILY_VARIABLE: &str = "DNS_UPDATER_IP_FAMILY";
+
+/// A command line and how long it may run.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandSpec {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseCommandError {
+    #[error("The command line is empty")]
+    Empty,
+    #[error("Invalid {0} option: {1}")]
+    InvalidOption(&'static str, String),
+}
+
+impl From<String> for CommandSpec {
+    fn from(command: String) -> Self {
+        CommandSpec {
+            command,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl FromStr for CommandSpec {
+    type Err = ParseCommandError;
+
+    /// Parses a command line, optionally preceded by `timeout=<seconds>`. The option comes
+    /// first since everything after the command belongs to the shell.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut command = s.trim_start();
+        let mut timeout = DEFAULT_TIMEOUT;
+
+        if let Some(rest) = command.strip_prefix("timeout=") {
+            let (value, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+            timeout = parse_timeout(value).ok_or_else(|| {
+                ParseCommandError::InvalidOption(
+                    "timeout",
+                    "Expected a positive number of seconds".to_string(),
+                )
+            })?;
+            command = rest.trim_start();
+        }
+
+        if command.trim().is_empty() {
+            return Err(ParseCommandError::Empty);
+        }
+
+        Ok(CommandSpec {
+            command: command.to_string(),
+            timeout,
+        })
+    }
+}
+
+/// Runs a command with the shell and reads the IP from its output, for routers that can only
+/// be asked with their own tools.
+pub struct CommandSource {
+    command: String,
+    timeout: Duration,
+}
+
+impl CommandSource {
+    pub fn new(command: String, timeout: Duration) -> Self {
+        CommandSource { command, timeout }
+    }
+
+    fn shell_command(&self) -> Command {
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(&self.command);
+            command
+        };
+        #[cfg(not(windows))]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(&self.command);
+            command
+        };
+
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        command
+    }
+}
+
+#[async_trait]
+impl IpSource for CommandSource {
+    fn name(&self) -> String {
+        format!("command:{}", self.command)
+    }
+
+    /// Every line of the output that is an address of `family` is a candidate, so a command
+    /// can print both its IPv4 and IPv6 address. The first one is used.
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        let mut command = self.shell_command();
+        command.env(
+            FAMILY_VARIABLE,
+            match family {
+                IpFamily::V4 => "4",
+                IpFamily::V6 => "6",
+            },
+        );
+
+        let output = tokio::time::timeout(self.timeout, command.output())
+            .await
+            .map_err(|_| {
+                WanIpError::QueryFailed(anyhow::anyhow!(
+                    "The command didn't finish within {} seconds",
+                    self.timeout.as_secs_f64()
+                ))
+            })??;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            return Err(WanIpError::QueryFailed(anyhow::anyhow!(
+                "The command failed with {}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.trim().parse::<IpAddr>().ok())
+            .find(|ip| IpFamily::of(ip) == family)
+            .ok_or_else(|| {
+                WanIpError::QueryFailed(anyhow::anyhow!(
+                    "The command didn't print an {family} address"
+                ))
+            })
+    }
+}
@@ -0,0 +1,192 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query, ResponseCode},
+    rr::{Name, RData, RecordType},
+};
+
+use super::{
+    udp::{self, connect, exchange},
+    IpFamily, IpSource, WanIpError,
+};
+
+const PORT: u16 = 53;
+
+/// A DNS name that resolves to the address of whoever asks, and the server to ask.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DnsLookup {
+    /// The name to look up.
+    pub name: String,
+    /// The server that answers with our address, as `host:port`.
+    pub server: String,
+    /// Whether the address is in a TXT record instead of an A or AAAA record.
+    pub txt: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Expected [txt:]<name>@<server>[:<port>]")]
+pub struct ParseDnsLookupError;
+
+impl FromStr for DnsLookup {
+    type Err = ParseDnsLookupError;
+
+    /// Parses `[txt:]<name>@<server>[:<port>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (txt, lookup) = match s.strip_prefix("txt:") {
+            Some(lookup) => (true, lookup),
+            None => (false, s),
+        };
+
+        match lookup.split_once('@') {
+            Some((name, server)) if !name.is_empty() && !server.is_empty() => Ok(DnsLookup {
+                name: name.to_string(),
+                server: udp::with_default_port(server, PORT),
+                txt,
+            }),
+            _ => Err(ParseDnsLookupError),
+        }
+    }
+}
+
+impl Display for DnsLookup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.txt {
+            write!(f, "txt:")?;
+        }
+
+        write!(f, "{}@{}", self.name, self.server)
+    }
+}
+
+/// The lookups that are used when none are specified.
+pub fn default_lookups() -> Vec<DnsLookup> {
+    vec![
+        DnsLookup {
+            name: "myip.opendns.com".to_string(),
+            server: "resolver1.opendns.com:53".to_string(),
+            txt: false,
+        },
+        DnsLookup {
+            name: "o-o.myaddr.l.google.com".to_string(),
+            server: "ns1.google.com:53".to_string(),
+            txt: true,
+        },
+    ]
+}
+
+/// Looks up names that special DNS servers answer with the address the query came from,
+/// like `myip.opendns.com` on the OpenDNS resolvers.
+pub struct DnsSource {
+    /// The lookups to try in order, or `None` to use the default lookups.
+    lookups: Option<Vec<DnsLookup>>,
+}
+
+impl DnsSource {
+    pub fn new(lookups: Option<Vec<DnsLookup>>) -> Self {
+        DnsSource { lookups }
+    }
+}
+
+#[async_trait]
+impl IpSource for DnsSource {
+    fn name(&self) -> String {
+        match &self.lookups {
+            Some(lookups) => format!(
+                "dns:{}",
+                lookups
+                    .iter()
+                    .map(DnsLookup::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            None => "dns".to_string(),
+        }
+    }
+
+    /// The query is sent over `family`, so the server sees (and answers with) the address
+    /// of that family.
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        let lookups = self.lookups.clone().unwrap_or_else(default_lookups);
+        let mut last_error = None;
+
+        for lookup in &lookups {
+            match query_lookup(lookup, family).await {
+                Ok(ip) => return Ok(ip),
+                Err(err) => {
+                    log::debug!("DNS lookup {lookup} failed: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(WanIpError::QueryFailed(last_error.unwrap_or_else(|| {
+            anyhow::anyhow!("There are no DNS lookups configured")
+        })))
+    }
+
+    async fn query_each(&self, family: IpFamily) -> Vec<(String, Result<IpAddr, WanIpError>)> {
+        let lookups = self.lookups.clone().unwrap_or_else(default_lookups);
+
+        join_all(lookups.iter().map(|lookup| async move {
+            (
+                format!("dns:{lookup}"),
+                query_lookup(lookup, family)
+                    .await
+                    .map_err(WanIpError::QueryFailed),
+            )
+        }))
+        .await
+    }
+}
+
+async fn query_lookup(lookup: &DnsLookup, family: IpFamily) -> anyhow::Result<IpAddr> {
+    let server = tokio::net::lookup_host(&lookup.server)
+        .await?
+        .find(|address| IpFamily::of(&address.ip()) == family)
+        .ok_or_else(|| anyhow::anyhow!("{} has no {family} address", lookup.server))?;
+
+    let ty = match (lookup.txt, family) {
+        (true, _) => RecordType::TXT,
+        (false, IpFamily::V4) => RecordType::A,
+        (false, IpFamily::V6) => RecordType::AAAA,
+    };
+    let name = Name::from_ascii(&lookup.name)?;
+
+    let mut message = Message::new();
+    message
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(false);
+    message.add_query(Query::query(name, ty));
+
+    let socket = connect(server).await?;
+    let response = exchange(&socket, &message.to_vec()?, |response| {
+        response.len() >= 2 && response[..2] == message.id().to_be_bytes()
+    })
+    .await?;
+    let response = Message::from_vec(&response)?;
+
+    if response.response_code() != ResponseCode::NoError {
+        anyhow::bail!("The server returned {}", response.response_code());
+    }
+
+    // Servers can add other TXT records, like the client subnet that Google's servers report
+    response
+        .answers()
+        .iter()
+        .flat_map(|record| match record.data() {
+            RData::A(a) => vec![IpAddr::from(a.0)],
+            RData::AAAA(aaaa) => vec![IpAddr::from(aaaa.0)],
+            RData::TXT(txt) => txt
+                .txt_data()
+                .iter()
+                .filter_map(|data| std::str::from_utf8(data).ok()?.parse().ok())
+                .collect(),
+            _ => vec![],
+        })
+        .find(|ip| IpFamily::of(ip) == family)
+        .ok_or_else(|| anyhow::anyhow!("The response has no {family} address"))
+}
@@ -0,0 +1,404 @@
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    str::FromStr,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use regex::Regex;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Url,
+};
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use super::{parse_timeout, IpFamily, IpSource, WanIpError};
+
+const DEFAULT_APIS: [&str; 2] = ["https://api.seeip.org", "https://api64.ipify.org"];
+const FILE_PATH: &str = "api_urls.txt";
+
+/// An IP echo endpoint and how to read the IP from its response, in the form
+/// `<url> [<option>=<value>]...` (see [`FromStr`] for the options).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HttpEndpoint {
+    pub url: Url,
+    pub extract: Extract,
+    /// Headers added to the request, as name and value.
+    pub headers: Vec<(String, String)>,
+    /// How long to wait for the response, no limit if `None`.
+    pub timeout: Option<Duration>,
+}
+
+/// Where the IP is in the response of an endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Extract {
+    /// The whole body is the IP, surrounding whitespace is ignored.
+    #[default]
+    Body,
+    /// The string at a JSON pointer (RFC 6901) into the body, like `/ip`.
+    JsonPointer(String),
+    /// The first capture group (or the whole match) of a regex on the body.
+    Regex(Pattern),
+    /// The value of a response header.
+    Header(String),
+}
+
+/// A compiled regex, compared and hashed by its pattern so endpoints can still be compared.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseEndpointError {
+    #[error("Invalid url: {0}")]
+    InvalidUrl(url::ParseError),
+    #[error("Unknown option \"{0}\", expected json, regex, response-header, header or timeout")]
+    UnknownOption(String),
+    #[error("Invalid {0} option: {1}")]
+    InvalidOption(&'static str, String),
+    #[error("Only one of json, regex and response-header can be used")]
+    MultipleExtracts,
+}
+
+impl From<Url> for HttpEndpoint {
+    fn from(url: Url) -> Self {
+        HttpEndpoint {
+            url,
+            extract: Extract::Body,
+            headers: vec![],
+            timeout: None,
+        }
+    }
+}
+
+impl FromStr for HttpEndpoint {
+    type Err = ParseEndpointError;
+
+    /// Parses a url followed by whitespace separated options:
+    /// - `json=<pointer>`, `regex=<pattern>` or `response-header=<name>` to extract the IP
+    /// - `header=<name>:<value>` to add a request header, can be repeated
+    /// - `timeout=<seconds>`
+    ///
+    /// Values that contain whitespace can be put in double quotes, like `header="Name: value"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = split_unquoted_whitespace(s);
+        let mut parts = parts.iter().map(String::as_str);
+        let url =
+            Url::parse(parts.next().unwrap_or_default()).map_err(ParseEndpointError::InvalidUrl)?;
+        let mut endpoint = HttpEndpoint::from(url);
+
+        for option in parts {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| ParseEndpointError::UnknownOption(option.to_string()))?;
+
+            let extract =
+                match key {
+                    "json" if value.is_empty() || value.starts_with('/') => {
+                        Extract::JsonPointer(value.to_string())
+                    }
+                    "json" => {
+                        return Err(ParseEndpointError::InvalidOption(
+                            "json",
+                            "The pointer should start with /".to_string(),
+                        ))
+                    }
+                    "regex" => Extract::Regex(Pattern(Regex::new(value).map_err(|err| {
+                        ParseEndpointError::InvalidOption("regex", err.to_string())
+                    })?)),
+                    "response-header" => {
+                        HeaderName::from_str(value).map_err(|err| {
+                            ParseEndpointError::InvalidOption("response-header", err.to_string())
+                        })?;
+                        Extract::Header(value.to_string())
+                    }
+                    "header" => {
+                        let (name, header_value) = value.split_once(':').ok_or_else(|| {
+                            ParseEndpointError::InvalidOption(
+                                "header",
+                                "Expected <name>:<value>".to_string(),
+                            )
+                        })?;
+                        let header_value = header_value.trim();
+                        HeaderName::from_str(name).map_err(|err| {
+                            ParseEndpointError::InvalidOption("header", err.to_string())
+                        })?;
+                        HeaderValue::from_str(header_value).map_err(|err| {
+                            ParseEndpointError::InvalidOption("header", err.to_string())
+                        })?;
+
+                        endpoint
+                            .headers
+                            .push((name.to_string(), header_value.to_string()));
+                        continue;
+                    }
+                    "timeout" => {
+                        endpoint.timeout = Some(parse_timeout(value).ok_or_else(|| {
+                            ParseEndpointError::InvalidOption(
+                                "timeout",
+                                "Expected a positive number of seconds".to_string(),
+                            )
+                        })?);
+                        continue;
+                    }
+                    _ => return Err(ParseEndpointError::UnknownOption(key.to_string())),
+                };
+
+            if endpoint.extract != Extract::Body {
+                return Err(ParseEndpointError::MultipleExtracts);
+            }
+
+            endpoint.extract = extract;
+        }
+
+        Ok(endpoint)
+    }
+}
+
+/// Splits `s` at whitespace that isn't in double quotes, and removes the quotes.
+fn split_unquoted_whitespace(s: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut quoted = false;
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !part.is_empty() {
+                    parts.push(std::mem::take(&mut part));
+                }
+            }
+            c => part.push(c),
+        }
+    }
+
+    if !part.is_empty() {
+        parts.push(part);
+    }
+
+    parts
+}
+
+impl TryFrom<String> for HttpEndpoint {
+    type Error = ParseEndpointError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for HttpEndpoint {
+    /// Only shows the url, the options are too noisy for log messages.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+/// Queries IP echo services over HTTP(S).
+pub struct HttpSource {
+    /// The endpoints to query, or `None` to use the ones in api_urls.txt.
+    endpoints: Option<Vec<HttpEndpoint>>,
+}
+
+impl HttpSource {
+    pub fn new(endpoints: Option<Vec<HttpEndpoint>>) -> Self {
+        HttpSource { endpoints }
+    }
+}
+
+#[async_trait]
+impl IpSource for HttpSource {
+    fn name(&self) -> String {
+        match &self.endpoints {
+            Some(endpoints) => format!(
+                "http:{}",
+                endpoints
+                    .iter()
+                    .map(HttpEndpoint::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            None => "http".to_string(),
+        }
+    }
+
+    /// The requests are made from a socket bound to `family`, so endpoints that answer on both
+    /// families (like api64.ipify.org) return the address of the requested family.
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        match &self.endpoints {
+            Some(endpoints) => query_wan_ip_from(endpoints, family).await,
+            None => query_wan_ip_from(&load_endpoints().await?, family).await,
+        }
+    }
+
+    async fn query_each(&self, family: IpFamily) -> Vec<(String, Result<IpAddr, WanIpError>)> {
+        let endpoints = match &self.endpoints {
+            Some(endpoints) => endpoints.clone(),
+            None => match load_endpoints().await {
+                Ok(endpoints) => endpoints,
+                Err(err) => return vec![(self.name(), Err(err))],
+            },
+        };
+
+        join_all(endpoints.iter().map(|endpoint| async move {
+            (
+                format!("http:{endpoint}"),
+                query_wan_ip_from(std::slice::from_ref(endpoint), family).await,
+            )
+        }))
+        .await
+    }
+}
+
+/// Queries the WAN IP of the given address family from the specified endpoints,
+/// returning the first valid response.
+async fn query_wan_ip_from(
+    endpoints: &[HttpEndpoint],
+    family: IpFamily,
+) -> Result<IpAddr, WanIpError> {
+    let mut last_error: Option<anyhow::Error> = None;
+
+    if endpoints.is_empty() {
+        return Err(WanIpError::NoApiEndpointsConfigured);
+    }
+
+    let client = reqwest::Client::builder()
+        .local_address(family.unspecified_address())
+        .build()
+        .map_err(|err| WanIpError::QueryFailed(err.into()))?;
+
+    for endpoint in endpoints {
+        match query_endpoint(&client, endpoint).await {
+            Ok(ip) if IpFamily::of(&ip) == family => return Ok(ip),
+            Ok(ip) => last_error = Some(anyhow::anyhow!("Expected an {family} address, got {ip}")),
+            Err(err) => {
+                log::debug!("Failed to query WAN {family} from {endpoint}: {err}");
+                last_error = Some(err);
+            }
+        }
+    }
+
+    Err(WanIpError::QueryFailed(last_error.unwrap_or_else(|| {
+        anyhow::anyhow!("Failed to query WAN IP")
+    })))
+}
+
+async fn query_endpoint(
+    client: &reqwest::Client,
+    endpoint: &HttpEndpoint,
+) -> anyhow::Result<IpAddr> {
+    let mut request = client.get(endpoint.url.clone());
+
+    for (name, value) in &endpoint.headers {
+        request = request.header(name, value);
+    }
+
+    if let Some(timeout) = endpoint.timeout {
+        request = request.timeout(timeout);
+    }
+
+    let response = request.send().await?.error_for_status()?;
+
+    let ip = match &endpoint.extract {
+        Extract::Body => response.text().await?,
+        Extract::JsonPointer(pointer) => {
+            let body = response.json::<serde_json::Value>().await?;
+
+            match body.pointer(pointer) {
+                Some(serde_json::Value::String(ip)) => ip.clone(),
+                Some(value) => anyhow::bail!("Expected a string at {pointer}, got {value}"),
+                None => anyhow::bail!("The response has no {pointer}"),
+            }
+        }
+        Extract::Regex(pattern) => {
+            let body = response.text().await?;
+            let captures = pattern
+                .0
+                .captures(&body)
+                .ok_or_else(|| anyhow::anyhow!("The response doesn't match {pattern}"))?;
+
+            captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .expect("A match should always have the whole match")
+                .as_str()
+                .to_string()
+        }
+        Extract::Header(name) => response
+            .headers()
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("The response has no {name} header"))?
+            .to_str()?
+            .to_string(),
+    };
+
+    Ok(ip.trim().parse::<IpAddr>()?)
+}
+
+/// Reads the endpoints in api_urls.txt, one per line, creating the file with the default
+/// endpoints if it doesn't exist. Empty lines and lines starting with `#` are ignored.
+async fn load_endpoints() -> Result<Vec<HttpEndpoint>, WanIpError> {
+    let file = File::open(FILE_PATH).await;
+
+    let endpoints = match file {
+        Ok(mut file) => {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).await?;
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| {
+                    line.parse::<HttpEndpoint>()
+                        .map_err(|err| WanIpError::InvalidEndpoint(line.to_string(), err))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        Err(err) => {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                let mut file = File::create(FILE_PATH).await?;
+
+                for api_url in DEFAULT_APIS {
+                    file.write_all(format!("{}\n", api_url).as_bytes()).await?;
+                }
+
+                DEFAULT_APIS
+                    .into_iter()
+                    .map(|api_url| Url::parse(api_url).map(HttpEndpoint::from))
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                return Err(WanIpError::Io(err));
+            }
+        }
+    };
+
+    Ok(endpoints)
+}
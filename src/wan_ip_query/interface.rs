@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use async_trait::async_trait;
+
+use super::{IpFamily, IpSource, WanIpError};
+
+// Address flags from linux/if_addr.h
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+
+/// Reads the WAN IP from an address that is assigned to a local network interface.
+/// Only addresses that can be reached from the internet are used, so link-local, private,
+/// ULA and temporary (privacy extension) IPv6 addresses are skipped.
+pub struct InterfaceSource {
+    interface: String,
+}
+
+impl InterfaceSource {
+    pub fn new(interface: String) -> Self {
+        InterfaceSource { interface }
+    }
+}
+
+#[async_trait]
+impl IpSource for InterfaceSource {
+    fn name(&self) -> String {
+        format!("interface:{}", self.interface)
+    }
+
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        let addresses = if_addrs::get_if_addrs()?
+            .into_iter()
+            .filter(|interface| interface.name == self.interface)
+            .map(|interface| interface.ip())
+            .collect::<Vec<_>>();
+
+        if addresses.is_empty() {
+            return Err(WanIpError::QueryFailed(anyhow::anyhow!(
+                "Interface {} does not exist or has no addresses",
+                self.interface
+            )));
+        }
+
+        let ipv6_flags = ipv6_address_flags(&self.interface);
+
+        addresses
+            .into_iter()
+            .find(|ip| match ip {
+                IpAddr::V4(ip) => family == IpFamily::V4 && is_public_ipv4(ip),
+                IpAddr::V6(ip) => {
+                    let flags = ipv6_flags.get(ip).copied().unwrap_or(0);
+                    let unusable =
+                        IFA_F_TEMPORARY | IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE;
+
+                    family == IpFamily::V6 && is_public_ipv6(ip) && flags & unusable == 0
+                }
+            })
+            .ok_or_else(|| {
+                WanIpError::QueryFailed(anyhow::anyhow!(
+                    "Interface {} has no public {family} address",
+                    self.interface
+                ))
+            })
+    }
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    !(ip.is_loopback() || ip.is_link_local() || ip.is_private() || ip.is_unspecified())
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    let is_link_local = first_segment & 0xffc0 == 0xfe80;
+    let is_unique_local = first_segment & 0xfe00 == 0xfc00;
+
+    !(ip.is_loopback() || ip.is_unspecified() || is_link_local || is_unique_local)
+}
+
+/// Returns the flags of the IPv6 addresses of an interface, which tell temporary
+/// addresses apart from stable ones. getifaddrs doesn't expose them, so they're read from
+/// /proc/net/if_inet6. Returns no flags if it can't be read (e.g. on other platforms).
+fn ipv6_address_flags(interface: &str) -> HashMap<Ipv6Addr, u32> {
+    // /proc/net is the network namespace of the process, but getifaddrs uses the one of the
+    // calling thread. Older kernels don't have /proc/thread-self.
+    let Ok(contents) = std::fs::read_to_string("/proc/thread-self/net/if_inet6")
+        .or_else(|_| std::fs::read_to_string("/proc/net/if_inet6"))
+    else {
+        return HashMap::new();
+    };
+
+    // Each line is: address, interface index, prefix length, scope, flags, interface name
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+
+            match fields.as_slice() {
+                [address, _, _, _, flags, name] if *name == interface => Some((
+                    Ipv6Addr::from(u128::from_str_radix(address, 16).ok()?),
+                    u32::from_str_radix(flags, 16).ok()?,
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
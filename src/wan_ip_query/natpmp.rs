@@ -0,0 +1,233 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::{
+    udp::{connect, exchange},
+    IpFamily, IpSource, WanIpError,
+};
+use async_trait::async_trait;
+use rand::Rng;
+
+/// The port that NAT-PMP and PCP servers listen on.
+pub const PORT: u16 = 5351;
+
+const NAT_PMP_VERSION: u8 = 0;
+const NAT_PMP_OP_EXTERNAL_ADDRESS: u8 = 0;
+
+const PCP_VERSION: u8 = 2;
+const PCP_OP_MAP: u8 = 1;
+const PCP_RESPONSE_BIT: u8 = 0x80;
+const PROTOCOL_UDP: u8 = 17;
+/// How long the temporary mapping that is used to learn the external address lives (seconds).
+const PCP_MAPPING_LIFETIME: u32 = 60;
+
+/// Asks the gateway for its external address with NAT-PMP (RFC 6886). IPv4 only.
+pub struct NatPmpSource {
+    /// The NAT-PMP server, the default gateway if `None`.
+    server: Option<SocketAddr>,
+}
+
+/// Asks the gateway for its external address with PCP (RFC 6887).
+/// PCP has no request for only the external address, so a short-lived UDP mapping is
+/// requested and deleted again right after.
+pub struct PcpSource {
+    /// The PCP server, the default gateway if `None`.
+    server: Option<SocketAddr>,
+}
+
+impl NatPmpSource {
+    pub fn new(server: Option<SocketAddr>) -> Self {
+        NatPmpSource { server }
+    }
+}
+
+impl PcpSource {
+    pub fn new(server: Option<SocketAddr>) -> Self {
+        PcpSource { server }
+    }
+}
+
+#[async_trait]
+impl IpSource for NatPmpSource {
+    fn name(&self) -> String {
+        match self.server {
+            Some(server) => format!("natpmp:{server}"),
+            None => "natpmp".to_string(),
+        }
+    }
+
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        if family != IpFamily::V4 {
+            return Err(query_failed("NAT-PMP only reports IPv4 addresses"));
+        }
+
+        let server = match self.server {
+            Some(server) => server,
+            None => SocketAddr::new(default_gateway()?.into(), PORT),
+        };
+
+        let socket = connect(server).await?;
+        let response = exchange(
+            &socket,
+            &[NAT_PMP_VERSION, NAT_PMP_OP_EXTERNAL_ADDRESS],
+            |response| {
+                response.len() >= 2
+                    && response[0] == NAT_PMP_VERSION
+                    && response[1] == PCP_RESPONSE_BIT | NAT_PMP_OP_EXTERNAL_ADDRESS
+            },
+        )
+        .await?;
+
+        // version, opcode, result code (2), seconds since start of epoch (4), address (4)
+        if response.len() < 12 {
+            return Err(query_failed("The NAT-PMP response is too short"));
+        }
+
+        let result_code = u16::from_be_bytes([response[2], response[3]]);
+        if result_code != 0 {
+            return Err(query_failed(format!(
+                "The NAT-PMP server returned result code {result_code}"
+            )));
+        }
+
+        let ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+        if ip.is_unspecified() {
+            return Err(query_failed("The gateway is not connected to the internet"));
+        }
+
+        Ok(ip.into())
+    }
+}
+
+#[async_trait]
+impl IpSource for PcpSource {
+    fn name(&self) -> String {
+        match self.server {
+            Some(server) => format!("pcp:{server}"),
+            None => "pcp".to_string(),
+        }
+    }
+
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        let server = match self.server {
+            Some(server) => server,
+            None if family == IpFamily::V4 => SocketAddr::new(default_gateway()?.into(), PORT),
+            None => {
+                return Err(query_failed(
+                    "The IPv6 PCP server must be specified, as pcp:<address>",
+                ))
+            }
+        };
+
+        let socket = connect(server).await?;
+        let client = socket.local_addr()?;
+        let nonce = rand::rng().random::<[u8; 12]>();
+
+        let response = exchange(
+            &socket,
+            &pcp_map_request(client, nonce, family, PCP_MAPPING_LIFETIME),
+            |response| {
+                response.len() >= 60
+                    && response[0] == PCP_VERSION
+                    && response[1] == PCP_RESPONSE_BIT | PCP_OP_MAP
+                    && response[24..36] == nonce
+            },
+        )
+        .await?;
+
+        let result_code = response[3];
+        if result_code != 0 {
+            return Err(query_failed(format!(
+                "The PCP server returned result code {result_code}"
+            )));
+        }
+
+        // Deleting the mapping is best effort, it expires on its own
+        let delete_request = pcp_map_request(client, nonce, family, 0);
+        if let Err(err) = socket.send(&delete_request).await {
+            log::debug!("Failed to delete the PCP mapping: {err}");
+        }
+
+        let address: [u8; 16] = response[44..60]
+            .try_into()
+            .expect("The address should always be 16 bytes");
+        let ip = Ipv6Addr::from(address);
+
+        match ip.to_ipv4_mapped() {
+            Some(ip) if family == IpFamily::V4 && !ip.is_unspecified() => Ok(ip.into()),
+            None if family == IpFamily::V6 && !ip.is_unspecified() => Ok(ip.into()),
+            _ => Err(query_failed(format!(
+                "The PCP server did not return an {family} address"
+            ))),
+        }
+    }
+}
+
+fn query_failed(message: impl std::fmt::Display) -> WanIpError {
+    WanIpError::QueryFailed(anyhow::anyhow!("{message}"))
+}
+
+/// Builds a PCP MAP request for the UDP port of `client`.
+fn pcp_map_request(
+    client: SocketAddr,
+    nonce: [u8; 12],
+    family: IpFamily,
+    lifetime: u32,
+) -> Vec<u8> {
+    // IPv4 addresses are sent as IPv4-mapped IPv6 addresses
+    let client_ip = match client.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    // The suggested external address tells the server which family we want
+    let suggested_ip = match family {
+        IpFamily::V4 => Ipv4Addr::UNSPECIFIED.to_ipv6_mapped(),
+        IpFamily::V6 => Ipv6Addr::UNSPECIFIED,
+    };
+
+    let mut request = Vec::with_capacity(60);
+    request.extend([PCP_VERSION, PCP_OP_MAP, 0, 0]);
+    request.extend(lifetime.to_be_bytes());
+    request.extend(client_ip.octets());
+    request.extend(nonce);
+    request.extend([PROTOCOL_UDP, 0, 0, 0]);
+    request.extend(client.port().to_be_bytes());
+    // Suggested external port, any
+    request.extend([0, 0]);
+    request.extend(suggested_ip.octets());
+
+    request
+}
+
+/// Returns the IPv4 default gateway from the kernel routing table.
+fn default_gateway() -> Result<Ipv4Addr, WanIpError> {
+    const RTF_GATEWAY: u32 = 0x2;
+
+    let routes = std::fs::read_to_string("/proc/net/route").map_err(|err| {
+        query_failed(format!(
+            "Failed to read the routing table, specify the gateway address instead: {err}"
+        ))
+    })?;
+
+    // Each line after the header is: interface, destination, gateway, flags, ...
+    // with addresses as hex numbers in host byte order
+    routes
+        .lines()
+        .skip(1)
+        .find_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let destination = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+
+            (destination == 0 && flags & RTF_GATEWAY != 0)
+                .then(|| Ipv4Addr::from(gateway.to_ne_bytes()))
+        })
+        .ok_or_else(|| query_failed("There is no IPv4 default gateway"))
+}
+
+/// Parses a NAT-PMP/PCP server address, with the port being optional.
+pub fn parse_server(server: &str) -> Result<SocketAddr, std::net::AddrParseError> {
+    server
+        .parse::<SocketAddr>()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, PORT)))
+}
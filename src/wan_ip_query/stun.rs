@@ -0,0 +1,180 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use rand::Rng;
+
+use super::{
+    udp::{connect, exchange},
+    IpFamily, IpSource, WanIpError,
+};
+
+/// The default port of STUN servers.
+pub const PORT: u16 = 3478;
+/// The servers that are asked when none are specified.
+pub const DEFAULT_SERVERS: [&str; 2] = ["stun.cloudflare.com:3478", "stun.l.google.com:19302"];
+
+const MAGIC_COOKIE: u32 = 0x2112A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ADDRESS_FAMILY_V4: u8 = 0x01;
+const ADDRESS_FAMILY_V6: u8 = 0x02;
+
+/// Asks STUN servers (RFC 5389) which address our requests come from.
+pub struct StunSource {
+    /// The servers to ask, as `host:port`, or `None` to use the default servers.
+    servers: Option<Vec<String>>,
+}
+
+impl StunSource {
+    pub fn new(servers: Option<Vec<String>>) -> Self {
+        StunSource { servers }
+    }
+}
+
+#[async_trait]
+impl IpSource for StunSource {
+    fn name(&self) -> String {
+        match &self.servers {
+            Some(servers) => format!("stun:{}", servers.join(",")),
+            None => "stun".to_string(),
+        }
+    }
+
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        let servers = match &self.servers {
+            Some(servers) => servers.iter().map(String::as_str).collect::<Vec<_>>(),
+            None => DEFAULT_SERVERS.to_vec(),
+        };
+        let mut last_error = None;
+
+        for server in servers {
+            match binding_request(server, family).await {
+                Ok(ip) => return Ok(ip),
+                Err(err) => {
+                    log::debug!("STUN server {server} failed: {err}");
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(WanIpError::QueryFailed(last_error.unwrap_or_else(|| {
+            anyhow::anyhow!("There are no STUN servers configured")
+        })))
+    }
+
+    async fn query_each(&self, family: IpFamily) -> Vec<(String, Result<IpAddr, WanIpError>)> {
+        let servers = match &self.servers {
+            Some(servers) => servers.iter().map(String::as_str).collect::<Vec<_>>(),
+            None => DEFAULT_SERVERS.to_vec(),
+        };
+
+        join_all(servers.into_iter().map(|server| async move {
+            (
+                format!("stun:{server}"),
+                binding_request(server, family)
+                    .await
+                    .map_err(WanIpError::QueryFailed),
+            )
+        }))
+        .await
+    }
+}
+
+/// Sends a Binding Request to `server` from a socket of `family` and returns the mapped address.
+async fn binding_request(server: &str, family: IpFamily) -> anyhow::Result<IpAddr> {
+    let server_address = tokio::net::lookup_host(server)
+        .await?
+        .find(|address| IpFamily::of(&address.ip()) == family)
+        .ok_or_else(|| anyhow::anyhow!("{server} has no {family} address"))?;
+
+    let socket = connect(server_address).await?;
+    let transaction_id = rand::rng().random::<[u8; 12]>();
+
+    let mut request = Vec::with_capacity(20);
+    request.extend(BINDING_REQUEST.to_be_bytes());
+    // No attributes
+    request.extend(0u16.to_be_bytes());
+    request.extend(MAGIC_COOKIE.to_be_bytes());
+    request.extend(transaction_id);
+
+    let response = exchange(&socket, &request, |response| {
+        response.len() >= 20
+            && response[4..8] == MAGIC_COOKIE.to_be_bytes()
+            && response[8..20] == transaction_id
+    })
+    .await?;
+
+    match u16::from_be_bytes([response[0], response[1]]) {
+        BINDING_SUCCESS_RESPONSE => parse_mapped_address(&response, &transaction_id),
+        BINDING_ERROR_RESPONSE => anyhow::bail!("The server returned an error response"),
+        message_type => anyhow::bail!("Unexpected STUN message type {message_type:#06x}"),
+    }
+}
+
+/// Reads the XOR-MAPPED-ADDRESS attribute of a Binding Response, or the MAPPED-ADDRESS
+/// attribute of servers that only implement the older RFC 3489.
+fn parse_mapped_address(response: &[u8], transaction_id: &[u8; 12]) -> anyhow::Result<IpAddr> {
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let mut attributes = response
+        .get(20..20 + length)
+        .ok_or_else(|| anyhow::anyhow!("The STUN response is truncated"))?;
+    let mut mapped_address = None;
+
+    // Each attribute is a type, a length and a value padded to a multiple of 4 bytes
+    while attributes.len() >= 4 {
+        let attribute_type = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let attribute_length = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes
+            .get(4..4 + attribute_length)
+            .ok_or_else(|| anyhow::anyhow!("A STUN attribute is truncated"))?;
+
+        match attribute_type {
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => {
+                let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+                mask.extend(transaction_id);
+
+                return parse_address(value, &mask);
+            }
+            ATTRIBUTE_MAPPED_ADDRESS => mapped_address = Some(parse_address(value, &[0; 16])?),
+            _ => {}
+        }
+
+        let padded_length = attribute_length.div_ceil(4) * 4;
+        attributes = attributes.get(4 + padded_length..).unwrap_or_default();
+    }
+
+    mapped_address.ok_or_else(|| anyhow::anyhow!("The STUN response has no mapped address"))
+}
+
+/// Parses the value of an address attribute, with the address XORed with `mask`.
+fn parse_address(value: &[u8], mask: &[u8]) -> anyhow::Result<IpAddr> {
+    let address = |length: usize| {
+        value
+            .get(4..4 + length)
+            .map(|address| {
+                address
+                    .iter()
+                    .zip(mask)
+                    .map(|(byte, mask)| byte ^ mask)
+                    .collect::<Vec<_>>()
+            })
+            .ok_or_else(|| anyhow::anyhow!("The STUN address attribute is truncated"))
+    };
+
+    // Reserved byte, family, port and address
+    match value.get(1) {
+        Some(&ADDRESS_FAMILY_V4) => {
+            let octets: [u8; 4] = address(4)?.try_into().expect("Length is checked");
+            Ok(Ipv4Addr::from(octets).into())
+        }
+        Some(&ADDRESS_FAMILY_V6) => {
+            let octets: [u8; 16] = address(16)?.try_into().expect("Length is checked");
+            Ok(Ipv6Addr::from(octets).into())
+        }
+        _ => anyhow::bail!("Unknown address family in the STUN response"),
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+
+use super::WanIpError;
+
+/// The timeout of the first attempt, doubled on every retransmission (like RFC 6886 section 3.1).
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const ATTEMPTS: u32 = 4;
+
+/// Binds a socket of the same family as `server` and connects it to `server`.
+pub(super) async fn connect(server: SocketAddr) -> Result<UdpSocket, WanIpError> {
+    let local_address = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+
+    let socket = UdpSocket::bind(local_address).await?;
+    socket.connect(server).await?;
+
+    Ok(socket)
+}
+
+/// Sends `request` and returns the first response that `is_response` accepts,
+/// retransmitting the request with a doubling timeout.
+pub(super) async fn exchange(
+    socket: &UdpSocket,
+    request: &[u8],
+    is_response: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, WanIpError> {
+    let mut buffer = [0; 1100];
+    let mut timeout = INITIAL_TIMEOUT;
+
+    for _ in 0..ATTEMPTS {
+        socket.send(request).await?;
+
+        let response = tokio::time::timeout(timeout, async {
+            loop {
+                let length = socket.recv(&mut buffer).await?;
+
+                if is_response(&buffer[..length]) {
+                    return Ok::<_, std::io::Error>(buffer[..length].to_vec());
+                }
+            }
+        })
+        .await;
+
+        match response {
+            Ok(response) => return Ok(response?),
+            Err(_) => timeout *= 2,
+        }
+    }
+
+    Err(WanIpError::QueryFailed(anyhow::anyhow!(
+        "{} did not respond",
+        socket.peer_addr()?
+    )))
+}
+
+/// Adds `port` to a `host[:port]` server address that doesn't have one.
+pub(super) fn with_default_port(server: &str, port: u16) -> String {
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        return SocketAddr::new(ip, port).to_string();
+    }
+
+    match server.rsplit_once(':') {
+        Some((_, server_port)) if server_port.parse::<u16>().is_ok() => server.to_string(),
+        _ => format!("{server}:{port}"),
+    }
+}
@@ -0,0 +1,234 @@
+use std::{net::IpAddr, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::Url;
+use tokio::net::UdpSocket;
+
+use super::{IpFamily, IpSource, WanIpError};
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+/// How long to wait for gateways to answer the discovery request.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The services that can be asked for the external address.
+const WAN_SERVICE_TYPES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+
+/// Asks the router for its external address with UPnP IGD (Internet Gateway Device).
+/// Routers only report their IPv4 address this way.
+pub struct UpnpSource {
+    /// The url of the gateway's device description, discovered with SSDP if `None`.
+    description_url: Option<Url>,
+    /// The description url found by the last discovery.
+    discovered_url: Mutex<Option<Url>>,
+    client: reqwest::Client,
+}
+
+impl UpnpSource {
+    pub fn new(description_url: Option<Url>) -> Self {
+        UpnpSource {
+            description_url,
+            discovered_url: Mutex::new(None),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("A client without custom TLS settings should always build"),
+        }
+    }
+
+    /// Sends an SSDP search to the LAN and returns the description url of the first gateway
+    /// that answers.
+    async fn discover(&self) -> Result<Url, WanIpError> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDRESS}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {SEARCH_TARGET}\r\n\r\n"
+        );
+        socket.send_to(request.as_bytes(), SSDP_ADDRESS).await?;
+
+        let mut buffer = [0; 2048];
+
+        tokio::time::timeout(DISCOVERY_TIMEOUT, async {
+            loop {
+                let (length, _) = socket.recv_from(&mut buffer).await?;
+                let response = String::from_utf8_lossy(&buffer[..length]);
+
+                let location = response.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("location")
+                        .then(|| Url::parse(value.trim()).ok())?
+                });
+
+                if let Some(location) = location {
+                    return Ok(location);
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            WanIpError::QueryFailed(anyhow::anyhow!("No UPnP gateway answered the discovery"))
+        })?
+    }
+
+    /// Reads the device description and returns the control url and type of the WAN service.
+    async fn find_wan_service(&self, description_url: &Url) -> anyhow::Result<(Url, String)> {
+        let description = self
+            .client
+            .get(description_url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // Relative control urls are relative to URLBase if it's set, otherwise the description
+        let base_url = match xml_elements(&description, "URLBase").first() {
+            Some(base) => Url::parse(base.trim())?,
+            None => description_url.clone(),
+        };
+
+        for service in xml_elements(&description, "service") {
+            let service_type = xml_elements(service, "serviceType")
+                .first()
+                .map(|ty| ty.trim().to_string());
+            let control_url = xml_elements(service, "controlURL")
+                .first()
+                .map(|url| url.trim().to_string());
+
+            if let (Some(service_type), Some(control_url)) = (service_type, control_url) {
+                if WAN_SERVICE_TYPES
+                    .iter()
+                    .any(|prefix| service_type.starts_with(prefix))
+                {
+                    return Ok((base_url.join(&control_url)?, service_type));
+                }
+            }
+        }
+
+        anyhow::bail!("The gateway has no WANIPConnection or WANPPPConnection service")
+    }
+
+    async fn get_external_ip_address(&self, description_url: &Url) -> anyhow::Result<IpAddr> {
+        let (control_url, service_type) = self.find_wan_service(description_url).await?;
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+            <s:Body><u:GetExternalIPAddress xmlns:u=\"{service_type}\"></u:GetExternalIPAddress></s:Body>\
+            </s:Envelope>"
+        );
+
+        let response = self
+            .client
+            .post(control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header(
+                "SOAPAction",
+                format!("\"{service_type}#GetExternalIPAddress\""),
+            )
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let address = xml_elements(&response, "NewExternalIPAddress")
+            .first()
+            .map(|address| address.trim().to_string())
+            .ok_or_else(|| anyhow::anyhow!("The gateway did not return an external address"))?;
+
+        match address.parse::<IpAddr>()? {
+            ip if ip.is_unspecified() => {
+                anyhow::bail!("The gateway is not connected to the internet")
+            }
+            ip => Ok(ip),
+        }
+    }
+}
+
+#[async_trait]
+impl IpSource for UpnpSource {
+    fn name(&self) -> String {
+        match &self.description_url {
+            Some(url) => format!("upnp:{url}"),
+            None => "upnp".to_string(),
+        }
+    }
+
+    async fn query(&self, family: IpFamily) -> Result<IpAddr, WanIpError> {
+        if family != IpFamily::V4 {
+            return Err(WanIpError::QueryFailed(anyhow::anyhow!(
+                "UPnP gateways only report their IPv4 address"
+            )));
+        }
+
+        if let Some(description_url) = &self.description_url {
+            return self
+                .get_external_ip_address(description_url)
+                .await
+                .map_err(WanIpError::QueryFailed);
+        }
+
+        // Reuse the last discovered gateway, and look for a new one if it stopped answering
+        let discovered_url = self
+            .discovered_url
+            .lock()
+            .expect("Discovered url lock should never be poisoned")
+            .clone();
+
+        if let Some(description_url) = discovered_url {
+            match self.get_external_ip_address(&description_url).await {
+                Ok(ip) => return Ok(ip),
+                Err(err) => log::debug!("UPnP gateway at {description_url} failed: {err}"),
+            }
+        }
+
+        let description_url = self.discover().await?;
+        let ip = self
+            .get_external_ip_address(&description_url)
+            .await
+            .map_err(WanIpError::QueryFailed)?;
+
+        *self
+            .discovered_url
+            .lock()
+            .expect("Discovered url lock should never be poisoned") = Some(description_url);
+
+        Ok(ip)
+    }
+}
+
+/// Returns the contents of every `<tag>` element in `xml`, ignoring namespace prefixes.
+/// This is only meant for the small and predictable documents of UPnP devices.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let mut elements = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+
+        let name = rest[..end].split_whitespace().next().unwrap_or_default();
+        let local_name = name.rsplit(':').next().unwrap_or(name);
+
+        if local_name != tag || name.ends_with('/') {
+            continue;
+        }
+
+        let content = &rest[end + 1..];
+        let closing_tag = format!("</{name}>");
+
+        if let Some(content_end) = content.find(&closing_tag) {
+            elements.push(&content[..content_end]);
+            rest = &content[content_end + closing_tag.len()..];
+        }
+    }
+
+    elements
+}
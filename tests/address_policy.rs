@@ -0,0 +1,85 @@
+use std::net::IpAddr;
+
+use dns_updater::address_policy::AddressPolicy;
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+/// Returns why `policy` rejects `address`, or `None` if it's accepted.
+fn rejection(policy: &AddressPolicy, address: &str) -> Option<String> {
+    policy.check(ip(address)).err().map(|err| err.to_string())
+}
+
+#[test]
+fn rejects_reserved_addresses_with_their_range() {
+    let policy = AddressPolicy::default();
+
+    assert_eq!(
+        rejection(&policy, "192.168.1.1").as_deref(),
+        Some("192.168.1.1 is a private address (192.168.0.0/16)")
+    );
+    assert_eq!(
+        rejection(&policy, "100.100.1.1").as_deref(),
+        Some("100.100.1.1 is a CG-NAT address (100.64.0.0/10)")
+    );
+
+    for (address, reason) in [
+        ("10.1.2.3", "a private address"),
+        ("172.31.255.255", "a private address"),
+        ("127.0.0.1", "a loopback address"),
+        ("169.254.10.20", "a link-local address"),
+        ("203.0.113.5", "a documentation address"),
+        ("239.255.255.250", "a multicast address"),
+        ("255.255.255.255", "a reserved address"),
+        ("::1", "a loopback address"),
+        ("fe80::1", "a link-local address"),
+        ("fd12:3456::1", "a unique local address"),
+        ("2001:db8::1", "a documentation address"),
+        ("2001:0:4136:e378::1", "a Teredo address"),
+        ("2002:c000:0204::1", "a 6to4 address"),
+        ("ff02::1", "a multicast address"),
+        ("::ffff:192.0.2.1", "an IPv4-mapped address"),
+    ] {
+        let rejected = policy.check(ip(address)).unwrap_err();
+
+        assert_eq!(rejected.reason, reason, "{address}");
+    }
+}
+
+#[test]
+fn accepts_public_addresses() {
+    let policy = AddressPolicy::default();
+
+    for address in [
+        "5.6.7.8",
+        "100.128.0.1",
+        "172.32.0.1",
+        "2a01:4f8::1",
+        "2001:4860::8888",
+    ] {
+        assert_eq!(rejection(&policy, address), None, "{address}");
+    }
+}
+
+#[test]
+fn allowed_ranges_are_exempt_and_denied_ranges_win() {
+    let policy = AddressPolicy {
+        denied: vec![
+            "10.0.99.0/24".parse().unwrap(),
+            "5.6.0.0/16".parse().unwrap(),
+        ],
+        allowed: vec!["10.0.0.0/8".parse().unwrap()],
+    };
+
+    assert_eq!(rejection(&policy, "10.0.1.1"), None);
+    assert_eq!(
+        rejection(&policy, "10.0.99.1").as_deref(),
+        Some("10.0.99.1 is in a denied range (10.0.99.0/24)")
+    );
+    assert_eq!(
+        rejection(&policy, "5.6.7.8").as_deref(),
+        Some("5.6.7.8 is in a denied range (5.6.0.0/16)")
+    );
+    assert!(rejection(&policy, "192.168.1.1").is_some());
+}
@@ -0,0 +1,71 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::netns::{add_interface, in_network_namespace, ip};
+use dns_updater::address_watch::AddressWatcher;
+
+/// Longer than the time the watcher waits for changes to settle.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn notices_added_and_removed_addresses() {
+    in_network_namespace(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            add_interface();
+
+            let mut watcher = AddressWatcher::new();
+
+            ip("addr add 192.0.2.1/24 dev wan0");
+            tokio::time::timeout(TIMEOUT, watcher.changed())
+                .await
+                .expect("The added IPv4 address should be noticed");
+
+            ip("addr add 2001:db8::1/64 dev wan0 nodad");
+            tokio::time::timeout(TIMEOUT, watcher.changed())
+                .await
+                .expect("The added IPv6 address should be noticed");
+
+            ip("addr del 192.0.2.1/24 dev wan0");
+            tokio::time::timeout(TIMEOUT, watcher.changed())
+                .await
+                .expect("The removed address should be noticed");
+        });
+    });
+}
+
+#[test]
+fn ignores_notifications_that_keep_the_addresses() {
+    in_network_namespace(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            add_interface();
+            ip("addr add 2001:db8::1/64 dev wan0 nodad");
+
+            let mut watcher = AddressWatcher::new();
+
+            // Like a router advertisement that renews the lifetime of an address
+            ip("addr change 2001:db8::1/64 dev wan0 valid_lft 3600 preferred_lft 1800");
+            // Link-local addresses don't affect the WAN IP
+            ip("addr add fe80::1234/64 dev wan0 nodad");
+
+            assert!(
+                tokio::time::timeout(TIMEOUT, watcher.changed())
+                    .await
+                    .is_err(),
+                "Only notifications that change the addresses should be reported"
+            );
+        });
+    });
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{
+    cloudflare::{MockCloudflare, API_TOKEN, READ_ONLY_TOKEN},
+    TempFile, WAN_IP,
+};
+use dns_updater::{
+    cloudflare::Cloudflare,
+    provider::{DnsProvider, ProviderError},
+    updater, AppError,
+};
+
+#[tokio::test]
+async fn updates_records_on_later_pages() {
+    let mock = MockCloudflare::start().await;
+    mock.set_page_size(2);
+
+    for i in 0..2 {
+        mock.add_zone(&format!("example{i}.com"));
+    }
+
+    let zone = mock.add_zone("example.com");
+
+    for i in 0..3 {
+        mock.add_record(
+            &zone,
+            "A",
+            &format!("host{i}.example.com"),
+            "1.2.3.4",
+            false,
+        );
+    }
+
+    let home = mock.add_record(&zone, "A", "home.example.com", "1.2.3.4", false);
+    let apex = mock.add_record(&zone, "A", "example.com", "1.2.3.4", false);
+    let state_file = TempFile::new("updates_records_on_later_pages");
+
+    updater::start(mock.settings(
+        &["home.example.com:A", "@.example.com:A"],
+        true,
+        &state_file,
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(mock.record(&zone, &home).content, WAN_IP);
+    assert_eq!(mock.record(&zone, &apex).content, WAN_IP);
+    assert_eq!(mock.record(&zone, &apex).name, "example.com");
+    assert_eq!(
+        mock.requests()
+            .iter()
+            .filter(|request| request.starts_with("GET "))
+            .collect::<Vec<_>>(),
+        [
+            "GET /zones?per_page=50&page=1",
+            "GET /zones?per_page=50&page=2",
+            &format!("GET /zones/{zone}/dns_records?per_page=5000&page=1"),
+            &format!("GET /zones/{zone}/dns_records?per_page=5000&page=2"),
+            &format!("GET /zones/{zone}/dns_records?per_page=5000&page=3"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn proxied_records_stay_proxied() {
+    let mock = MockCloudflare::start().await;
+    let zone = mock.add_zone("example.com");
+    let home = mock.add_record(&zone, "A", "home.example.com", "1.2.3.4", true);
+    let state_file = TempFile::new("proxied_records_stay_proxied");
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ttl.ttl = Some(300);
+    updater::start(settings).await.unwrap();
+
+    let patches = mock.patches();
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["content"], WAN_IP);
+    assert_eq!(patches[0]["proxied"], true);
+    // The TTL of proxied records is managed by Cloudflare
+    assert_eq!(patches[0]["ttl"], 1);
+
+    let record = mock.record(&zone, &home);
+    assert_eq!(record.content, WAN_IP);
+    assert!(record.proxied);
+}
+
+#[tokio::test]
+async fn proxied_records_are_up_to_date_with_a_ttl_policy() {
+    let mock = MockCloudflare::start().await;
+    let zone = mock.add_zone("example.com");
+    mock.add_record(&zone, "A", "home.example.com", WAN_IP, true);
+    let state_file = TempFile::new("proxied_records_are_up_to_date_with_a_ttl_policy");
+
+    for _ in 0..2 {
+        let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+        settings.entries[0].ttl.ttl = Some(300);
+        updater::start(settings).await.unwrap();
+    }
+
+    // The state of the first run is enough for the second one, although the record keeps the
+    // TTL that Cloudflare chose
+    assert_eq!(
+        mock.requests(),
+        [
+            "GET /zones?per_page=50&page=1",
+            &format!("GET /zones/{zone}/dns_records?per_page=5000&page=1"),
+        ]
+    );
+    assert!(mock.patches().is_empty());
+}
+
+#[tokio::test]
+async fn creates_missing_records_with_their_full_name() {
+    let mock = MockCloudflare::start().await;
+    let zone = mock.add_zone("example.com");
+    let state_file = TempFile::new("creates_missing_records_with_their_full_name");
+
+    let mut settings = mock.settings(&["new.example.com:A"], true, &state_file);
+    settings.entries[0].create_ttl = Some(120);
+    updater::start(settings).await.unwrap();
+
+    let records = mock.records(&zone);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].name, "new.example.com");
+    assert_eq!(records[0].content, WAN_IP);
+    assert_eq!(records[0].ttl, 120);
+}
+
+#[tokio::test]
+async fn invalid_api_token_fails_validation() {
+    let mock = MockCloudflare::start().await;
+    mock.add_zone("example.com");
+    let state_file = TempFile::new("invalid_api_token_fails_validation");
+
+    let result = updater::start(mock.settings_with_token(
+        &["home.example.com:A"],
+        true,
+        &state_file,
+        "wrong-token",
+    ))
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(AppError::TestFailedKeyValidation("Cloudflare"))
+    ));
+}
+
+#[tokio::test]
+async fn token_without_permissions_fails_validation() {
+    let mock = MockCloudflare::start().await;
+    mock.add_zone("example.com");
+    let state_file = TempFile::new("token_without_permissions_fails_validation");
+
+    let result = updater::start(mock.settings_with_token(
+        &["home.example.com:A"],
+        true,
+        &state_file,
+        READ_ONLY_TOKEN,
+    ))
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(AppError::TestFailedKeyValidation("Cloudflare"))
+    ));
+}
+
+#[tokio::test]
+async fn reports_unsuccessful_responses_with_a_success_status() {
+    let mock = MockCloudflare::start().await;
+    let zone = mock.add_zone("example.com");
+    let home = mock.add_record(&zone, "A", "home.example.com", "1.2.3.4", false);
+    mock.inject_error(StatusCode::OK, 1000, "Something went wrong");
+    let state_file = TempFile::new("reports_unsuccessful_responses");
+
+    let result = updater::start(mock.settings(&["home.example.com:A"], false, &state_file)).await;
+
+    assert!(matches!(
+        result,
+        Err(AppError::OtherError(err)) if err.to_string().contains("Something went wrong (1000)")
+    ));
+    assert_eq!(mock.record(&zone, &home).content, "1.2.3.4");
+}
+
+#[tokio::test]
+async fn deletes_records() {
+    let mock = MockCloudflare::start().await;
+    let zone_id = mock.add_zone("example.com");
+    mock.add_record(&zone_id, "A", "home.example.com", "1.2.3.4", false);
+    let other = mock.add_record(&zone_id, "A", "other.example.com", "1.2.3.4", false);
+    // Cloudflare has inherent methods with the same names as the trait methods
+    let provider: &dyn DnsProvider = &Cloudflare::new(API_TOKEN.to_string(), &mock.url).unwrap();
+
+    let zone = provider.list_zones().await.unwrap().remove(0);
+    let records = provider
+        .list_records(&zone, &["home.example.com"])
+        .await
+        .unwrap();
+    let home = records.iter().find(|record| record.name == "home").unwrap();
+    provider.delete_record(&zone, home).await.unwrap();
+
+    assert_eq!(
+        mock.records(&zone_id)
+            .iter()
+            .map(|record| record.id.clone())
+            .collect::<Vec<_>>(),
+        [other]
+    );
+    assert!(matches!(
+        provider.delete_record(&zone, home).await,
+        Err(ProviderError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn connection_failures_are_reported_as_request_errors() {
+    // Nothing listens on the discard port
+    let provider: &dyn DnsProvider =
+        &Cloudflare::new(API_TOKEN.to_string(), "http://127.0.0.1:9").unwrap();
+
+    let err = provider.list_zones().await.unwrap_err();
+
+    assert!(
+        err.to_string().starts_with("ReqwestError: "),
+        "Unexpected error: {err}"
+    );
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use dns_updater::{config::Settings, provider::ProviderConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use super::{query_param, TempFile, WAN_IP};
+
+pub const API_TOKEN: &str = "mock-api-token";
+/// A valid token that lacks the permissions to read zones.
+pub const READ_ONLY_TOKEN: &str = "mock-read-only-token";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockRecord {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The fully qualified name, like the API returns it.
+    pub name: String,
+    pub content: String,
+    pub ttl: u32,
+    pub proxied: bool,
+}
+
+#[derive(Debug)]
+struct MockState {
+    /// Ids and names of the zones.
+    zones: Vec<(String, String)>,
+    records: BTreeMap<String, Vec<MockRecord>>,
+    next_id: u32,
+    page_size: usize,
+    injected: VecDeque<(StatusCode, serde_json::Value)>,
+    /// Every request that was received, as "METHOD /path?query".
+    requests: Vec<String>,
+    /// The bodies of the PATCH requests.
+    patches: Vec<serde_json::Value>,
+}
+
+impl MockState {
+    /// Returns a new id in the format of Cloudflare ids.
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:032x}", self.next_id)
+    }
+}
+
+pub struct MockCloudflare {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockCloudflare {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(Mutex::new(MockState {
+            zones: vec![],
+            records: BTreeMap::new(),
+            next_id: 0,
+            page_size: 20,
+            injected: VecDeque::new(),
+            requests: vec![],
+            patches: vec![],
+        }));
+
+        let app = Router::new().fallback(handle).with_state(state.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        MockCloudflare { url, state, server }
+    }
+
+    /// Adds a zone and returns its id.
+    pub fn add_zone(&self, name: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id();
+        state.zones.push((id.clone(), name.to_string()));
+        state.records.entry(id.clone()).or_default();
+
+        id
+    }
+
+    /// Adds a record with a fully qualified `name` and returns its id.
+    pub fn add_record(
+        &self,
+        zone_id: &str,
+        ty: &str,
+        name: &str,
+        content: &str,
+        proxied: bool,
+    ) -> String {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id();
+
+        state
+            .records
+            .get_mut(zone_id)
+            .expect("Zone should be added before its records")
+            .push(MockRecord {
+                id: id.clone(),
+                ty: ty.to_string(),
+                name: name.to_string(),
+                content: content.to_string(),
+                // Cloudflare reports the automatic TTL of proxied records as 1
+                ttl: if proxied { 1 } else { 1800 },
+                proxied,
+            });
+
+        id
+    }
+
+    pub fn records(&self, zone_id: &str) -> Vec<MockRecord> {
+        self.state.lock().unwrap().records[zone_id].clone()
+    }
+
+    pub fn record(&self, zone_id: &str, id: &str) -> MockRecord {
+        self.records(zone_id)
+            .into_iter()
+            .find(|record| record.id == id)
+            .unwrap()
+    }
+
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.lock().unwrap().page_size = page_size;
+    }
+
+    /// Returns an error envelope with `status` for the next request instead of handling it.
+    pub fn inject_error(&self, status: StatusCode, code: i64, message: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .injected
+            .push_back((status, api_errors(code, message)));
+    }
+
+    /// The API requests that were received, as "METHOD /path?query". WAN IP queries are not
+    /// included.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn patches(&self) -> Vec<serde_json::Value> {
+        self.state.lock().unwrap().patches.clone()
+    }
+
+    /// Settings that update `domains` through this server, with the WAN IP also queried from it.
+    pub fn settings(&self, domains: &[&str], apply: bool, state_file: &TempFile) -> Settings {
+        self.settings_with_token(domains, apply, state_file, API_TOKEN)
+    }
+
+    pub fn settings_with_token(
+        &self,
+        domains: &[&str],
+        apply: bool,
+        state_file: &TempFile,
+        api_token: &str,
+    ) -> Settings {
+        super::settings(
+            ProviderConfig::Cloudflare {
+                api_token: api_token.to_string(),
+                api_base: self.url.clone(),
+            },
+            &self.url,
+            domains,
+            apply,
+            state_file,
+        )
+    }
+}
+
+impl Drop for MockCloudflare {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn api_errors(code: i64, message: &str) -> serde_json::Value {
+    json!({
+        "success": false,
+        "errors": [{ "code": code, "message": message }],
+        "messages": [],
+        "result": null,
+    })
+}
+
+fn error(status: StatusCode, code: i64, message: &str) -> Response {
+    (status, Json(api_errors(code, message))).into_response()
+}
+
+fn success<T: Serialize>(result: T) -> Response {
+    Json(json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result": result,
+    }))
+    .into_response()
+}
+
+/// Returns one page of `items` in the format of the list endpoints.
+fn paginate<T: Serialize>(state: &MockState, uri: &Uri, items: &[T]) -> Response {
+    let page = query_param(uri, "page").unwrap_or(1);
+    let start = (page - 1) * state.page_size;
+    let page_items = items
+        .iter()
+        .skip(start)
+        .take(state.page_size)
+        .collect::<Vec<_>>();
+
+    Json(json!({
+        "success": true,
+        "errors": [],
+        "messages": [],
+        "result": page_items,
+        "result_info": {
+            "page": page,
+            "per_page": state.page_size,
+            "count": page_items.len(),
+            "total_count": items.len(),
+            "total_pages": items.len().div_ceil(state.page_size).max(1),
+        },
+    }))
+    .into_response()
+}
+
+async fn handle(
+    State(state): State<Arc<Mutex<MockState>>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if uri.path() == "/ip" {
+        return WAN_IP.into_response();
+    }
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(format!("{method} {uri}"));
+
+    if let Some((status, body)) = state.injected.pop_front() {
+        return (status, Json(body)).into_response();
+    }
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(API_TOKEN) => {}
+        Some(READ_ONLY_TOKEN) => {
+            return error(
+                StatusCode::FORBIDDEN,
+                9109,
+                "Unauthorized to access requested resource",
+            )
+        }
+        _ => return error(StatusCode::UNAUTHORIZED, 10000, "Authentication error"),
+    }
+
+    let segments = uri.path().trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match (method, segments.as_slice()) {
+        (Method::GET, ["zones"]) => {
+            let zones = state
+                .zones
+                .iter()
+                .map(|(id, name)| json!({ "id": id, "name": name, "status": "active" }))
+                .collect::<Vec<_>>();
+
+            paginate(&state, &uri, &zones)
+        }
+        (Method::GET, ["zones", zone_id, "dns_records"]) => match state.records.get(*zone_id) {
+            Some(records) => paginate(&state, &uri, records),
+            None => error(StatusCode::NOT_FOUND, 7003, "Could not route to zone"),
+        },
+        (Method::POST, ["zones", zone_id, "dns_records"]) => {
+            let request = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            let record = MockRecord {
+                id: state.next_id(),
+                ty: request["type"].as_str().unwrap().to_string(),
+                name: request["name"].as_str().unwrap().to_string(),
+                content: request["content"].as_str().unwrap().to_string(),
+                ttl: request["ttl"].as_u64().unwrap() as u32,
+                proxied: request["proxied"].as_bool().unwrap_or(false),
+            };
+
+            match state.records.get_mut(*zone_id) {
+                Some(records) => {
+                    records.push(record.clone());
+                    success(record)
+                }
+                None => error(StatusCode::NOT_FOUND, 7003, "Could not route to zone"),
+            }
+        }
+        (Method::PATCH, ["zones", zone_id, "dns_records", id]) => {
+            let request = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            state.patches.push(request.clone());
+
+            let record = state
+                .records
+                .get_mut(*zone_id)
+                .and_then(|records| records.iter_mut().find(|record| record.id == *id));
+
+            match record {
+                Some(record) => {
+                    if let Some(content) = request["content"].as_str() {
+                        record.content = content.to_string();
+                    }
+
+                    if let Some(ttl) = request["ttl"].as_u64() {
+                        record.ttl = ttl as u32;
+                    }
+
+                    if let Some(proxied) = request["proxied"].as_bool() {
+                        record.proxied = proxied;
+                    }
+
+                    success(record.clone())
+                }
+                None => error(StatusCode::NOT_FOUND, 81044, "Record does not exist"),
+            }
+        }
+        (Method::DELETE, ["zones", zone_id, "dns_records", id]) => {
+            let Some(records) = state.records.get_mut(*zone_id) else {
+                return error(StatusCode::NOT_FOUND, 7003, "Could not route to zone");
+            };
+
+            let count = records.len();
+            records.retain(|record| record.id != *id);
+
+            if records.len() < count {
+                success(json!({ "id": id }))
+            } else {
+                error(StatusCode::NOT_FOUND, 81044, "Record does not exist")
+            }
+        }
+        _ => error(StatusCode::NOT_FOUND, 7000, "No route for that URI"),
+    }
+}
@@ -0,0 +1,460 @@
+//! In-memory mocks of the parts of the DigitalOcean and Cloudflare APIs that the updater uses,
+//! and helpers for running the updater against them.
+
+// Every test crate only uses the mock of the provider it tests
+#![allow(dead_code)]
+
+pub mod cloudflare;
+#[cfg(target_os = "linux")]
+pub mod netns;
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::{Arc, Mutex, Once},
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use dns_updater::{
+    address_policy::AddressPolicy,
+    config::{RecordEntry, Settings, TtlPolicy},
+    domain_arg::DomainArg,
+    provider::ProviderConfig,
+    wan_ip_query::IpSourceSpec,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{net::TcpListener, task::JoinHandle};
+use url::Url;
+
+pub const API_KEY: &str = "mock-api-key";
+pub const WAN_IP: &str = "5.6.7.8";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MockRecord {
+    pub id: i32,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub name: String,
+    pub data: String,
+    pub ttl: i32,
+}
+
+/// A response that is returned instead of handling a request.
+#[derive(Debug, Clone)]
+pub struct InjectedResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl InjectedResponse {
+    pub fn rate_limited(retry_after: u64) -> Self {
+        InjectedResponse {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            headers: vec![("Retry-After", retry_after.to_string())],
+        }
+    }
+
+    pub fn server_error() -> Self {
+        InjectedResponse {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            headers: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    base_url: String,
+    domains: Vec<String>,
+    records: BTreeMap<String, Vec<MockRecord>>,
+    next_record_id: i32,
+    page_size: usize,
+    injected: VecDeque<InjectedResponse>,
+    /// Every request that was received, as "METHOD /path".
+    requests: Vec<String>,
+}
+
+pub struct MockDigitalOcean {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockDigitalOcean {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let state = Arc::new(Mutex::new(MockState {
+            base_url: url.clone(),
+            domains: vec![],
+            records: BTreeMap::new(),
+            next_record_id: 1,
+            page_size: 20,
+            injected: VecDeque::new(),
+            requests: vec![],
+        }));
+
+        let app = Router::new().fallback(handle).with_state(state.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        MockDigitalOcean { url, state, server }
+    }
+
+    pub fn add_domain(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.domains.push(name.to_string());
+        state.records.entry(name.to_string()).or_default();
+    }
+
+    /// Adds a record and returns its id.
+    pub fn add_record(&self, domain: &str, ty: &str, name: &str, data: &str) -> i32 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_record_id;
+        state.next_record_id += 1;
+
+        state
+            .records
+            .get_mut(domain)
+            .expect("Domain should be added before its records")
+            .push(MockRecord {
+                id,
+                ty: ty.to_string(),
+                name: name.to_string(),
+                data: data.to_string(),
+                ttl: 1800,
+            });
+
+        id
+    }
+
+    pub fn records(&self, domain: &str) -> Vec<MockRecord> {
+        self.state.lock().unwrap().records[domain].clone()
+    }
+
+    pub fn record(&self, domain: &str, id: i32) -> MockRecord {
+        self.records(domain)
+            .into_iter()
+            .find(|record| record.id == id)
+            .unwrap()
+    }
+
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state.lock().unwrap().page_size = page_size;
+    }
+
+    /// Returns `response` for the next request instead of handling it.
+    pub fn inject(&self, response: InjectedResponse) {
+        self.state.lock().unwrap().injected.push_back(response);
+    }
+
+    /// The API requests that were received, as "METHOD /path". WAN IP queries are not included.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_with_method(&self, method: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|request| request.starts_with(&format!("{method} ")))
+            .count()
+    }
+
+    pub fn clear_requests(&self) {
+        self.state.lock().unwrap().requests.clear();
+    }
+
+    /// Settings that update `domains` through this server, with the WAN IP also queried from it.
+    pub fn settings(&self, domains: &[&str], apply: bool, state_file: &TempFile) -> Settings {
+        self.settings_with_key(domains, apply, state_file, API_KEY)
+    }
+
+    pub fn settings_with_key(
+        &self,
+        domains: &[&str],
+        apply: bool,
+        state_file: &TempFile,
+        api_key: &str,
+    ) -> Settings {
+        settings(
+            ProviderConfig::DigitalOcean {
+                api_key: api_key.to_string(),
+                api_base: self.url.clone(),
+            },
+            &self.url,
+            domains,
+            apply,
+            state_file,
+        )
+    }
+}
+
+impl Drop for MockDigitalOcean {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Settings that update `domains` through `provider`, with the WAN IP queried from the `/ip`
+/// endpoint of the mock at `mock_url`.
+pub fn settings(
+    provider: ProviderConfig,
+    mock_url: &str,
+    domains: &[&str],
+    apply: bool,
+    state_file: &TempFile,
+) -> Settings {
+    Settings {
+        update_interval: None,
+        watch_addresses: false,
+        apply,
+        skip_warning: true,
+        state_file: state_file.0.clone(),
+        max_staleness: 1440,
+        ip_quorum: None,
+        ip_confirmations: NonZeroU32::MIN,
+        ip_confirm_delay: Duration::from_secs(60),
+        ip_confirm_duration: Duration::ZERO,
+        address_policy: AddressPolicy::default(),
+        providers: BTreeMap::from([("mock".to_string(), provider)]),
+        entries: vec![RecordEntry {
+            provider: "mock".to_string(),
+            domains: domains
+                .iter()
+                .map(|domain| domain.parse::<DomainArg>().unwrap())
+                .collect(),
+            create_ttl: None,
+            ttl: TtlPolicy::default(),
+            ipv6_prefix_length: 64,
+            ip_sources: vec![IpSourceSpec::Http(Some(vec![Url::parse(&format!(
+                "{mock_url}/ip"
+            ))
+            .unwrap()
+            .into()]))],
+        }],
+    }
+}
+
+/// The messages that were logged by any test of the crate, since the logger is global.
+static LOGS: Mutex<Vec<(log::Level, String)>> = Mutex::new(vec![]);
+
+struct CapturingLogger;
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LOGS.lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Starts capturing log messages, see [`logs_containing`].
+pub fn capture_logs() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        log::set_logger(&CapturingLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    });
+}
+
+/// The captured messages that contain `text`, like a domain name only used by one test.
+pub fn logs_containing(text: &str) -> Vec<(log::Level, String)> {
+    LOGS.lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, message)| message.contains(text))
+        .cloned()
+        .collect()
+}
+
+/// A path in the temp directory that is removed when dropped.
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "dns-updater-test-{}-{name}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn error(status: StatusCode, id: &str, message: &str) -> Response {
+    (status, Json(json!({ "id": id, "message": message }))).into_response()
+}
+
+fn query_param(uri: &Uri, name: &str) -> Option<usize> {
+    uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value.parse().ok())?
+    })
+}
+
+/// Returns one page of `items` in the format of the list endpoints.
+fn paginate<T: Serialize>(state: &MockState, uri: &Uri, key: &str, items: &[T]) -> Response {
+    let page = query_param(uri, "page").unwrap_or(1);
+    let start = (page - 1) * state.page_size;
+    let page_items = items
+        .iter()
+        .skip(start)
+        .take(state.page_size)
+        .collect::<Vec<_>>();
+
+    let mut pages = serde_json::Map::new();
+    if start + state.page_size < items.len() {
+        pages.insert(
+            "next".to_string(),
+            format!("{}{}?page={}", state.base_url, uri.path(), page + 1).into(),
+        );
+    }
+
+    Json(json!({
+        key: page_items,
+        "links": { "pages": pages },
+        "meta": { "total": items.len() },
+    }))
+    .into_response()
+}
+
+async fn handle(
+    State(state): State<Arc<Mutex<MockState>>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if uri.path() == "/ip" {
+        return WAN_IP.into_response();
+    }
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(format!("{method} {}", uri.path()));
+
+    if let Some(injected) = state.injected.pop_front() {
+        let mut response = error(injected.status, "injected", "Injected error");
+
+        for (name, value) in injected.headers {
+            response.headers_mut().insert(name, value.parse().unwrap());
+        }
+
+        return response;
+    }
+
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .is_some_and(|value| value == format!("Bearer {API_KEY}").as_str());
+
+    if !authorized {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Unable to authenticate you",
+        );
+    }
+
+    let segments = uri.path().trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match (method, segments.as_slice()) {
+        (Method::GET, ["v2", "domains"]) => {
+            let domains = state
+                .domains
+                .iter()
+                .map(|name| json!({ "name": name, "ttl": 1800, "zone_file": null }))
+                .collect::<Vec<_>>();
+
+            paginate(&state, &uri, "domains", &domains)
+        }
+        (Method::GET, ["v2", "domains", domain, "records"]) => match state.records.get(*domain) {
+            Some(records) => paginate(&state, &uri, "domain_records", records),
+            None => error(StatusCode::NOT_FOUND, "not_found", "Domain not found"),
+        },
+        (Method::POST, ["v2", "domains", domain, "records"]) => {
+            let request = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            let record = MockRecord {
+                id: state.next_record_id,
+                ty: request["type"].as_str().unwrap().to_string(),
+                name: request["name"].as_str().unwrap().to_string(),
+                data: request["data"].as_str().unwrap().to_string(),
+                ttl: request["ttl"].as_i64().unwrap() as i32,
+            };
+            state.next_record_id += 1;
+
+            match state.records.get_mut(*domain) {
+                Some(records) => {
+                    records.push(record.clone());
+                    (
+                        StatusCode::CREATED,
+                        Json(json!({ "domain_record": record })),
+                    )
+                        .into_response()
+                }
+                None => error(StatusCode::NOT_FOUND, "not_found", "Domain not found"),
+            }
+        }
+        (Method::PATCH, ["v2", "domains", domain, "records", id]) => {
+            let request = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            let record = state.records.get_mut(*domain).and_then(|records| {
+                records
+                    .iter_mut()
+                    .find(|record| id.parse() == Ok(record.id))
+            });
+
+            match record {
+                Some(record) => {
+                    if let Some(data) = request["data"].as_str() {
+                        record.data = data.to_string();
+                    }
+
+                    if let Some(ttl) = request["ttl"].as_i64() {
+                        record.ttl = ttl as i32;
+                    }
+
+                    Json(json!({ "domain_record": record })).into_response()
+                }
+                None => error(StatusCode::NOT_FOUND, "not_found", "Record not found"),
+            }
+        }
+        (Method::DELETE, ["v2", "domains", domain, "records", id]) => {
+            let Some(records) = state.records.get_mut(*domain) else {
+                return error(StatusCode::NOT_FOUND, "not_found", "Domain not found");
+            };
+
+            let count = records.len();
+            records.retain(|record| id.parse() != Ok(record.id));
+
+            if records.len() < count {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                error(StatusCode::NOT_FOUND, "not_found", "Record not found")
+            }
+        }
+        _ => error(StatusCode::NOT_FOUND, "not_found", "Unknown endpoint"),
+    }
+}
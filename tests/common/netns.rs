@@ -0,0 +1,37 @@
+//! Helpers for tests that need their own network interfaces.
+
+use std::process::Command;
+
+use nix::sched::{unshare, CloneFlags};
+
+/// Runs `test` in a new network namespace, so interfaces can be created without touching the
+/// host. The test is skipped if that isn't allowed, which needs root or CAP_SYS_ADMIN.
+pub fn in_network_namespace(test: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(move || {
+        // Namespaces are per thread, so only this thread and the commands it runs are moved
+        if let Err(err) = unshare(CloneFlags::CLONE_NEWNET) {
+            eprintln!("Skipping, failed to create a network namespace: {err}");
+            return;
+        }
+
+        test();
+    })
+    .join()
+    .unwrap()
+}
+
+pub fn ip(args: &str) {
+    let status = Command::new("ip")
+        .args(args.split(' '))
+        .status()
+        .expect("Failed to run ip");
+
+    assert!(status.success(), "ip {args} failed");
+}
+
+/// Adds a wan0 interface. A veth pair is used because the dummy module isn't available everywhere.
+pub fn add_interface() {
+    ip("link add wan0 type veth peer name lan0");
+    ip("link set lan0 up");
+    ip("link set wan0 up");
+}
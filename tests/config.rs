@@ -0,0 +1,206 @@
+mod common;
+
+use clap::{CommandFactory, FromArgMatches};
+use common::TempFile;
+use dns_updater::{
+    config::{self, ConfigError, Settings},
+    wan_ip_query::{Extract, IpFamily, IpSourceSpec},
+    AppArgs,
+};
+
+/// Resolves the settings of a config file with `contents`, and the command line `args`.
+fn resolve(name: &str, contents: &str, args: &[&str]) -> Result<Settings, ConfigError> {
+    let config_file = TempFile::new(name);
+    std::fs::write(&config_file.0, contents).unwrap();
+
+    let matches = AppArgs::command()
+        .try_get_matches_from(
+            ["dns-updater", "--config", config_file.0.to_str().unwrap()]
+                .into_iter()
+                .chain(args.iter().copied()),
+        )
+        .unwrap();
+    let args = AppArgs::from_arg_matches(&matches).unwrap();
+
+    config::resolve_settings(&args, &matches)
+}
+
+const PROVIDER: &str = r#"
+[providers.home]
+type = "digitalocean"
+api_key = "key"
+"#;
+
+#[test]
+fn families_restrict_the_domains_of_an_entry() {
+    let settings = resolve(
+        "families_restrict_the_domains_of_an_entry",
+        &format!(
+            r#"{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com", "vpn.example.com:AAAA"]
+families = ["A"]
+"#
+        ),
+        &[],
+    )
+    .unwrap();
+
+    let domains = &settings.entries[0].domains;
+    assert_eq!(domains[0].families(), [IpFamily::V4]);
+    assert_eq!(domains[1].families(), [IpFamily::V6]);
+}
+
+#[test]
+fn empty_families_are_rejected() {
+    let result = resolve(
+        "empty_families_are_rejected",
+        &format!(
+            r#"{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+families = []
+"#
+        ),
+        &[],
+    );
+
+    assert!(matches!(result, Err(ConfigError::EmptyFamilies(1))));
+}
+
+#[test]
+fn unreachable_quorum_is_rejected() {
+    let contents = format!(
+        r#"ip_sources = ["stun:stun.example.com", "dns"]
+{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+"#
+    );
+
+    // One vote for the STUN server, and one for each default DNS lookup
+    let settings = resolve("reachable_quorum", &contents, &["--ip-quorum", "3"]).unwrap();
+    assert_eq!(settings.ip_quorum.map(|quorum| quorum.get()), Some(3));
+
+    let result = resolve("unreachable_quorum", &contents, &["--ip-quorum", "4"]);
+    assert!(matches!(result, Err(ConfigError::UnreachableQuorum(4, 3))));
+}
+
+#[test]
+fn duplicate_endpoints_only_vote_once() {
+    let contents = format!(
+        r#"{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+ip_urls = ["https://ip.example.com", "https://ip.example.com", "https://ip.example.net"]
+"#
+    );
+
+    let settings = resolve("duplicate_endpoints", &contents, &["--ip-quorum", "2"]).unwrap();
+    assert_eq!(settings.ip_quorum.map(|quorum| quorum.get()), Some(2));
+
+    let result = resolve("duplicate_endpoints", &contents, &["--ip-quorum", "3"]);
+    assert!(matches!(result, Err(ConfigError::UnreachableQuorum(3, 2))));
+}
+
+#[test]
+fn ttl_policy_wins_over_the_create_ttl() {
+    common::capture_logs();
+    let records = r#"
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+create_missing = true
+ttl = 60
+
+[[records]]
+provider = "home"
+domains = ["vpn.example.com"]
+create_missing = true
+min_ttl = 60
+"#;
+    let warnings = || common::logs_containing("instead of the --create-ttl");
+
+    // The default create TTL is replaced without a warning
+    let settings = resolve(
+        "ttl_policy_wins_over_the_default_create_ttl",
+        &format!("{PROVIDER}{records}"),
+        &[],
+    )
+    .unwrap();
+    assert_eq!(settings.entries[0].create_ttl, Some(60));
+    assert_eq!(settings.entries[1].create_ttl, Some(300));
+    assert!(warnings().is_empty());
+
+    let settings = resolve(
+        "ttl_policy_wins_over_the_create_ttl",
+        &format!("create_ttl = 120\n{PROVIDER}{records}"),
+        &[],
+    )
+    .unwrap();
+    assert_eq!(settings.entries[0].create_ttl, Some(60));
+    assert_eq!(settings.entries[1].create_ttl, Some(120));
+    assert_eq!(
+        warnings(),
+        [(
+            log::Level::Warn,
+            "The records created for record entry 1 get the TTL 60 of --ttl/--min-ttl/--max-ttl \
+             instead of the --create-ttl 120"
+                .to_string()
+        )]
+    );
+}
+
+#[test]
+fn quorum_of_sources_that_use_api_urls_is_checked_when_querying() {
+    let result = resolve(
+        "quorum_of_sources_that_use_api_urls",
+        &format!(
+            r#"ip_sources = ["http", "stun"]
+ip_quorum = 5
+{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+"#
+        ),
+        &[],
+    );
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn ip_sources_are_not_split_at_commas() {
+    let settings = resolve(
+        "ip_sources_are_not_split_at_commas",
+        &format!(
+            r#"{PROVIDER}
+[[records]]
+provider = "home"
+domains = ["home.example.com"]
+"#
+        ),
+        &[
+            "--ip-source",
+            r"http:https://ip.example.com regex=([0-9]{1,3}(?:\.[0-9]{1,3}){3})",
+            "--ip-source",
+            "stun",
+        ],
+    )
+    .unwrap();
+
+    let [IpSourceSpec::Http(Some(endpoints)), IpSourceSpec::Stun(None)] =
+        settings.entries[0].ip_sources.as_slice()
+    else {
+        panic!("Unexpected sources {:?}", settings.entries[0].ip_sources);
+    };
+    assert!(matches!(
+        &endpoints[0].extract,
+        Extract::Regex(pattern) if pattern.to_string() == r"([0-9]{1,3}(?:\.[0-9]{1,3}){3})"
+    ));
+}
@@ -0,0 +1,529 @@
+mod common;
+
+use common::{
+    capture_logs, logs_containing, InjectedResponse, MockDigitalOcean, TempFile, API_KEY, WAN_IP,
+};
+use std::time::Duration;
+
+use dns_updater::{
+    config::TtlPolicy,
+    digitalocean::DigitalOcean,
+    provider::{DnsProvider, ProviderError},
+    updater,
+    wan_ip_query::IpSourceSpec,
+    AppError,
+};
+
+#[tokio::test]
+async fn updates_outdated_records() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let apex = mock.add_record("example.com", "A", "@", "1.2.3.4");
+    let other = mock.add_record("example.com", "A", "other", "1.2.3.4");
+    let state_file = TempFile::new("updates_outdated_records");
+
+    updater::start(mock.settings(
+        &["home.example.com:A", "@.example.com:A"],
+        true,
+        &state_file,
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+    assert_eq!(mock.record("example.com", apex).data, WAN_IP);
+    assert_eq!(mock.record("example.com", other).data, "1.2.3.4");
+    assert_eq!(mock.requests_with_method("PATCH"), 2);
+}
+
+#[tokio::test]
+async fn preview_does_not_change_records() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("preview_does_not_change_records");
+
+    updater::start(mock.settings(&["home.example.com:A"], false, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, "1.2.3.4");
+    assert_eq!(mock.requests_with_method("PATCH"), 0);
+    assert!(!state_file.0.exists());
+}
+
+#[tokio::test]
+async fn up_to_date_records_are_not_patched() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    mock.add_record("example.com", "A", "home", WAN_IP);
+    let state_file = TempFile::new("up_to_date_records_are_not_patched");
+
+    updater::start(mock.settings(&["home.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.requests_with_method("PATCH"), 0);
+}
+
+#[tokio::test]
+async fn creates_missing_records_when_enabled() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let state_file = TempFile::new("creates_missing_records_when_enabled");
+
+    let mut settings = mock.settings(&["new.example.com:A"], true, &state_file);
+    settings.entries[0].create_ttl = Some(120);
+    updater::start(settings).await.unwrap();
+
+    let records = mock.records("example.com");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].ty, "A");
+    assert_eq!(records[0].name, "new");
+    assert_eq!(records[0].data, WAN_IP);
+    assert_eq!(records[0].ttl, 120);
+}
+
+#[tokio::test]
+async fn missing_records_are_not_created_by_default() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let state_file = TempFile::new("missing_records_are_not_created_by_default");
+
+    updater::start(mock.settings(&["new.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert!(mock.records("example.com").is_empty());
+    assert_eq!(mock.requests_with_method("POST"), 0);
+}
+
+#[tokio::test]
+async fn follows_pagination() {
+    let mock = MockDigitalOcean::start().await;
+    mock.set_page_size(2);
+
+    for i in 0..5 {
+        mock.add_domain(&format!("example{i}.com"));
+    }
+
+    for i in 0..5 {
+        mock.add_record("example4.com", "A", &format!("host{i}"), "1.2.3.4");
+    }
+
+    let last = mock.add_record("example4.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("follows_pagination");
+
+    updater::start(mock.settings(&["home.example4.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example4.com", last).data, WAN_IP);
+    assert_eq!(mock.requests_with_method("GET"), 3 + 3);
+}
+
+#[tokio::test]
+async fn matches_the_most_specific_zone() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    mock.add_domain("lab.example.com");
+    let outer = mock.add_record("example.com", "A", "host.lab", "1.2.3.4");
+    let inner = mock.add_record("lab.example.com", "A", "host", "1.2.3.4");
+    let state_file = TempFile::new("matches_the_most_specific_zone");
+
+    updater::start(mock.settings(&["host.lab.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", outer).data, "1.2.3.4");
+    assert_eq!(mock.record("lab.example.com", inner).data, WAN_IP);
+}
+
+#[tokio::test]
+async fn retries_rate_limited_requests() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    mock.inject(InjectedResponse::rate_limited(0));
+    mock.inject(InjectedResponse::rate_limited(0));
+    let state_file = TempFile::new("retries_rate_limited_requests");
+
+    updater::start(mock.settings(&["home.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+    assert_eq!(
+        mock.requests(),
+        [
+            "GET /v2/domains",
+            "GET /v2/domains",
+            "GET /v2/domains",
+            "GET /v2/domains/example.com/records",
+            "PATCH /v2/domains/example.com/records/1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn retries_server_errors() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    mock.inject(InjectedResponse::server_error());
+    let state_file = TempFile::new("retries_server_errors");
+
+    updater::start(mock.settings(&["home.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+    assert_eq!(mock.requests_with_method("GET"), 3);
+}
+
+#[tokio::test]
+async fn invalid_api_key_fails_validation() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let state_file = TempFile::new("invalid_api_key_fails_validation");
+
+    let result = updater::start(mock.settings_with_key(
+        &["home.example.com:A"],
+        true,
+        &state_file,
+        "wrong-key",
+    ))
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(AppError::TestFailedKeyValidation("DigitalOcean"))
+    ));
+}
+
+#[tokio::test]
+async fn unchanged_wan_ip_skips_api_calls() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("unchanged_wan_ip_skips_api_calls");
+
+    updater::start(mock.settings(&["home.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+    assert!(state_file.0.exists());
+    mock.clear_requests();
+
+    updater::start(mock.settings(&["home.example.com:A"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert!(mock.requests().is_empty());
+
+    // A domain that isn't in the state yet is still updated
+    let other = mock.add_record("example.com", "A", "other", "1.2.3.4");
+
+    updater::start(mock.settings(
+        &["home.example.com:A", "other.example.com:A"],
+        true,
+        &state_file,
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(mock.record("example.com", other).data, WAN_IP);
+    assert_eq!(mock.requests_with_method("PATCH"), 1);
+}
+
+#[tokio::test]
+async fn combines_the_wan_prefix_with_interface_identifiers() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let router = mock.add_record("example.com", "AAAA", "router", "2001:db8::1");
+    let nas = mock.add_record("example.com", "AAAA", "nas", "2001:db8::10");
+    let pc = mock.add_record("example.com", "AAAA", "pc", "2001:db8::20");
+    let state_file = TempFile::new("combines_the_wan_prefix_with_interface_identifiers");
+
+    let mut settings = mock.settings(
+        &[
+            "router.example.com:AAAA",
+            "nas.example.com:AAAA=::10",
+            "pc.example.com:AAAA=00:11:22:33:44:55",
+        ],
+        true,
+        &state_file,
+    );
+    settings.entries[0].ip_sources = vec![IpSourceSpec::Command(
+        "echo 2001:db8:1:2::1".to_string().into(),
+    )];
+    settings.address_policy.allowed = vec!["2001:db8::/32".parse().unwrap()];
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(mock.record("example.com", router).data, "2001:db8:1:2::1");
+    assert_eq!(mock.record("example.com", nas).data, "2001:db8:1:2::10");
+    assert_eq!(
+        mock.record("example.com", pc).data,
+        "2001:db8:1:2:211:22ff:fe33:4455"
+    );
+}
+
+#[tokio::test]
+async fn refuses_to_publish_private_wan_ips() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("refuses_to_publish_private_wan_ips");
+
+    let mut settings = mock.settings(&["home.example.com:A"], false, &state_file);
+    settings.entries[0].ip_sources =
+        vec![IpSourceSpec::Command("echo 192.168.1.1".to_string().into())];
+
+    assert!(matches!(
+        updater::start(settings).await,
+        Err(AppError::RejectedWanIp(rejected)) if rejected.reason == "a private address"
+    ));
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn reserved_wan_ips_are_fatal_when_applying() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("reserved_wan_ips_are_fatal_when_applying");
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ip_sources =
+        vec![IpSourceSpec::Command("echo 100.64.1.1".to_string().into())];
+
+    let result = updater::start(settings).await;
+    assert!(matches!(
+        &result,
+        Err(AppError::RejectedWanIp(rejected)) if rejected.reason == "a CG-NAT address"
+    ));
+    assert_eq!(result.unwrap_err().error_code(), 4);
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn wan_ips_in_denied_ranges_are_retried_when_applying() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("wan_ips_in_denied_ranges_are_retried_when_applying");
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.address_policy.denied = vec!["5.6.7.0/24".parse().unwrap()];
+
+    // The updater keeps waiting for an address outside of the denied range
+    let result = tokio::time::timeout(Duration::from_millis(300), updater::start(settings)).await;
+    assert!(result.is_err());
+    assert_eq!(mock.record("example.com", home).data, "1.2.3.4");
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn updates_the_ttl_of_up_to_date_records() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", WAN_IP);
+    let state_file = TempFile::new("updates_the_ttl_of_up_to_date_records");
+
+    let ttl = TtlPolicy {
+        ttl: Some(300),
+        ..TtlPolicy::default()
+    };
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ttl = ttl;
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(mock.record("example.com", home).ttl, 300);
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+    assert_eq!(mock.requests_with_method("PATCH"), 1);
+    mock.clear_requests();
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ttl = ttl;
+    updater::start(settings).await.unwrap();
+
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn clamps_the_ttl_to_the_maximum() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", WAN_IP);
+    let state_file = TempFile::new("clamps_the_ttl_to_the_maximum");
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ttl.max_ttl = Some(600);
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(mock.record("example.com", home).ttl, 600);
+}
+
+#[tokio::test]
+async fn preview_does_not_change_the_ttl() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", WAN_IP);
+    let state_file = TempFile::new("preview_does_not_change_the_ttl");
+
+    let mut settings = mock.settings(&["home.example.com:A"], false, &state_file);
+    settings.entries[0].ttl.ttl = Some(300);
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(mock.record("example.com", home).ttl, 1800);
+    assert_eq!(mock.requests_with_method("PATCH"), 0);
+}
+
+#[tokio::test]
+async fn changes_from_the_persisted_wan_ip_are_confirmed_after_a_restart() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let home = mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let state_file = TempFile::new("changes_from_the_persisted_wan_ip_are_confirmed");
+
+    let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+    settings.entries[0].ip_sources = vec![IpSourceSpec::Command("echo 1.2.3.4".to_string().into())];
+    updater::start(settings).await.unwrap();
+
+    // The WAN IP changed while the updater was stopped
+    let confirming_settings = || {
+        let mut settings = mock.settings(&["home.example.com:A"], true, &state_file);
+        settings.ip_confirmations = 2.try_into().unwrap();
+        settings.ip_confirm_delay = Duration::from_millis(500);
+        settings
+    };
+
+    let first_query = tokio::time::timeout(
+        Duration::from_millis(200),
+        updater::start(confirming_settings()),
+    );
+    assert!(first_query.await.is_err());
+    assert_eq!(mock.record("example.com", home).data, "1.2.3.4");
+
+    updater::start(confirming_settings()).await.unwrap();
+
+    assert_eq!(mock.record("example.com", home).data, WAN_IP);
+}
+
+#[tokio::test]
+async fn deletes_records() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    mock.add_record("example.com", "A", "home", "1.2.3.4");
+    let other = mock.add_record("example.com", "A", "other", "1.2.3.4");
+    // DigitalOcean has inherent methods with the same names as the trait methods
+    let provider: &dyn DnsProvider = &DigitalOcean::new(API_KEY.to_string(), &mock.url).unwrap();
+
+    let zone = provider.list_zones().await.unwrap().remove(0);
+    let records = provider
+        .list_records(&zone, &["home.example.com"])
+        .await
+        .unwrap();
+    let home = records.iter().find(|record| record.name == "home").unwrap();
+    provider.delete_record(&zone, home).await.unwrap();
+
+    assert_eq!(
+        mock.records("example.com")
+            .iter()
+            .map(|record| record.id)
+            .collect::<Vec<_>>(),
+        [other]
+    );
+    assert!(matches!(
+        provider.delete_record(&zone, home).await,
+        Err(ProviderError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn updates_a_and_aaaa_records_of_a_domain_in_the_same_cycle() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let a = mock.add_record("example.com", "A", "dualstack", "1.2.3.4");
+    let aaaa = mock.add_record("example.com", "AAAA", "dualstack", "2a01:4f8::1");
+    let state_file = TempFile::new("updates_a_and_aaaa_records_of_a_domain_in_the_same_cycle");
+
+    let mut settings = mock.settings(&["dualstack.example.com"], true, &state_file);
+    settings.entries[0].ip_sources = vec![IpSourceSpec::Command(
+        format!("printf '{WAN_IP}\\n2a01:4f8::2\\n'").into(),
+    )];
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(mock.record("example.com", a).data, WAN_IP);
+    assert_eq!(mock.record("example.com", aaaa).data, "2a01:4f8::2");
+    // The zone and its records are only listed once for both families
+    assert_eq!(
+        mock.requests(),
+        [
+            "GET /v2/domains".to_string(),
+            "GET /v2/domains/example.com/records".to_string(),
+            format!("PATCH /v2/domains/example.com/records/{a}"),
+            format!("PATCH /v2/domains/example.com/records/{aaaa}"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn ipv6_lookup_failures_still_update_a_records() {
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let a = mock.add_record("example.com", "A", "v4only", "1.2.3.4");
+    let aaaa = mock.add_record("example.com", "AAAA", "v4only", "2a01:4f8::1");
+    let state_file = TempFile::new("ipv6_lookup_failures_still_update_a_records");
+
+    // The mock only answers WAN IP queries with an IPv4 address
+    updater::start(mock.settings(&["v4only.example.com"], true, &state_file))
+        .await
+        .unwrap();
+
+    assert_eq!(mock.record("example.com", a).data, WAN_IP);
+    assert_eq!(mock.record("example.com", aaaa).data, "2a01:4f8::1");
+    assert_eq!(mock.requests_with_method("PATCH"), 1);
+}
+
+#[tokio::test]
+async fn a_only_domains_ignore_missing_aaaa_records() {
+    capture_logs();
+    let mock = MockDigitalOcean::start().await;
+    mock.add_domain("example.com");
+    let explicit = mock.add_record("example.com", "A", "explicit-a", "1.2.3.4");
+    let implicit = mock.add_record("example.com", "A", "implicit-a", "1.2.3.4");
+    let state_file = TempFile::new("a_only_domains_ignore_missing_aaaa_records");
+
+    for apply in [false, true] {
+        let mut settings = mock.settings(
+            &["explicit-a.example.com:A", "implicit-a.example.com"],
+            apply,
+            &state_file,
+        );
+        settings.entries[0].ip_sources = vec![IpSourceSpec::Command(
+            format!("printf '{WAN_IP}\\n2a01:4f8::2\\n'").into(),
+        )];
+        updater::start(settings).await.unwrap();
+    }
+
+    assert_eq!(mock.record("example.com", explicit).data, WAN_IP);
+    assert_eq!(mock.record("example.com", implicit).data, WAN_IP);
+    assert_eq!(mock.requests_with_method("POST"), 0);
+
+    for domain in ["explicit-a.example.com", "implicit-a.example.com"] {
+        let logs = logs_containing(domain);
+        assert!(!logs.is_empty());
+        assert!(
+            logs.iter().all(|(level, _)| *level > log::Level::Warn),
+            "Unexpected warnings for {domain}: {logs:?}"
+        );
+    }
+
+    // The domain that is restricted to A records never mentions its missing AAAA record
+    assert!(logs_containing("explicit-a.example.com")
+        .iter()
+        .all(|(_, message)| !message.contains("AAAA")));
+}
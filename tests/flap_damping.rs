@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use dns_updater::flap_damping::{FlapDamper, Verdict};
+
+fn ips(ips: &[(&'static str, &str)]) -> HashMap<&'static str, IpAddr> {
+    ips.iter()
+        .map(|(key, ip)| (*key, ip.parse().unwrap()))
+        .collect()
+}
+
+fn damper(confirmations: u32) -> FlapDamper<&'static str> {
+    FlapDamper::new(NonZeroU32::new(confirmations).unwrap(), Duration::ZERO)
+}
+
+#[test]
+fn accepts_changes_that_are_seen_enough_times_in_a_row() {
+    let mut damper = damper(3);
+
+    // There's nothing to compare the first IPs to
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.8")])),
+        Verdict::Accepted
+    );
+
+    let changed = ips(&[("v4", "5.6.7.9")]);
+
+    assert_eq!(
+        damper.observe(&changed),
+        Verdict::Pending {
+            seen: 1,
+            needed: 3,
+            remaining: Duration::ZERO,
+        }
+    );
+    assert_eq!(
+        damper.observe(&changed),
+        Verdict::Pending {
+            seen: 2,
+            needed: 3,
+            remaining: Duration::ZERO,
+        }
+    );
+    assert_eq!(damper.observe(&changed), Verdict::Accepted);
+    assert_eq!(damper.observe(&changed), Verdict::Accepted);
+}
+
+#[test]
+fn forgets_changes_that_flap_back() {
+    let mut damper = damper(2);
+    let original = ips(&[("v4", "5.6.7.8"), ("v6", "2a01:4f8::1")]);
+
+    assert_eq!(damper.observe(&original), Verdict::Accepted);
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.9"), ("v6", "2a01:4f8::1")])),
+        Verdict::Pending {
+            seen: 1,
+            needed: 2,
+            remaining: Duration::ZERO,
+        }
+    );
+    assert_eq!(damper.observe(&original), Verdict::Accepted);
+    // The earlier change doesn't count towards a new one
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.9")])),
+        Verdict::Pending {
+            seen: 1,
+            needed: 2,
+            remaining: Duration::ZERO,
+        }
+    );
+    // Neither does a change to yet another IP
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.10")])),
+        Verdict::Pending {
+            seen: 1,
+            needed: 2,
+            remaining: Duration::ZERO,
+        }
+    );
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.10")])),
+        Verdict::Accepted
+    );
+}
+
+#[test]
+fn new_and_missing_ips_are_not_changes() {
+    let mut damper = damper(2);
+
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.8")])),
+        Verdict::Accepted
+    );
+    // The IPv6 query failed before, and now the IPv4 query fails
+    assert_eq!(
+        damper.observe(&ips(&[("v6", "2a01:4f8::1")])),
+        Verdict::Accepted
+    );
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.8"), ("v6", "2a01:4f8::1")])),
+        Verdict::Accepted
+    );
+}
+
+#[test]
+fn a_single_confirmation_accepts_every_change() {
+    let mut damper = damper(1);
+
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.8")])),
+        Verdict::Accepted
+    );
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.9")])),
+        Verdict::Accepted
+    );
+}
+
+#[test]
+fn changes_have_to_be_seen_for_the_minimum_duration() {
+    let mut damper = FlapDamper::new(NonZeroU32::new(2).unwrap(), Duration::from_secs(300));
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    assert_eq!(
+        damper.observe_at(&ips(&[("v4", "5.6.7.8")]), at(0)),
+        Verdict::Accepted
+    );
+
+    let changed = ips(&[("v4", "5.6.7.9")]);
+
+    assert_eq!(
+        damper.observe_at(&changed, at(60)),
+        Verdict::Pending {
+            seen: 1,
+            needed: 2,
+            remaining: Duration::from_secs(300),
+        }
+    );
+    // Seen often enough, but not for long enough
+    assert_eq!(
+        damper.observe_at(&changed, at(120)),
+        Verdict::Pending {
+            seen: 2,
+            needed: 2,
+            remaining: Duration::from_secs(240),
+        }
+    );
+    assert_eq!(damper.observe_at(&changed, at(360)), Verdict::Accepted);
+}
+
+#[test]
+fn a_minimum_duration_alone_damps_changes() {
+    let mut damper = FlapDamper::new(NonZeroU32::MIN, Duration::from_secs(300));
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    assert_eq!(
+        damper.observe_at(&ips(&[("v4", "5.6.7.8")]), at(0)),
+        Verdict::Accepted
+    );
+    assert_eq!(
+        damper.observe_at(&ips(&[("v4", "5.6.7.9")]), at(60)),
+        Verdict::Pending {
+            seen: 1,
+            needed: 1,
+            remaining: Duration::from_secs(300),
+        }
+    );
+    // Changing to yet another IP restarts the duration
+    assert_eq!(
+        damper.observe_at(&ips(&[("v4", "5.6.7.10")]), at(300)),
+        Verdict::Pending {
+            seen: 1,
+            needed: 1,
+            remaining: Duration::from_secs(300),
+        }
+    );
+    assert_eq!(
+        damper.observe_at(&ips(&[("v4", "5.6.7.10")]), at(600)),
+        Verdict::Accepted
+    );
+}
+
+#[test]
+fn changes_from_accepted_ips_have_to_be_confirmed() {
+    let mut damper = damper(2);
+    damper.accept(ips(&[("v4", "5.6.7.8")]));
+
+    // Like the first query after a restart, compared to the IPs from the state
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.9"), ("v6", "2a01:4f8::1")])),
+        Verdict::Pending {
+            seen: 1,
+            needed: 2,
+            remaining: Duration::ZERO,
+        }
+    );
+    assert_eq!(
+        damper.observe(&ips(&[("v4", "5.6.7.8"), ("v6", "2a01:4f8::1")])),
+        Verdict::Accepted
+    );
+}
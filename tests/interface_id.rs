@@ -0,0 +1,64 @@
+use std::net::Ipv6Addr;
+
+use dns_updater::{domain_arg::DomainArg, interface_id::InterfaceId, wan_ip_query::IpFamily};
+
+fn ipv6(address: &str) -> Ipv6Addr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn applies_suffixes_to_the_prefix() {
+    let suffix = "::10".parse::<InterfaceId>().unwrap();
+
+    assert_eq!(suffix, InterfaceId::Suffix(ipv6("::10")));
+    assert_eq!(
+        suffix.apply(ipv6("2001:db8:1:2:aaaa:bbbb:cccc:dddd"), 64),
+        ipv6("2001:db8:1:2::10")
+    );
+}
+
+#[test]
+fn suffixes_can_pick_the_subnet_of_a_shorter_prefix() {
+    let suffix = "0:0:0:10::5".parse::<InterfaceId>().unwrap();
+
+    assert_eq!(
+        suffix.apply(ipv6("2001:db8:1:2ff::1"), 56),
+        ipv6("2001:db8:1:210::5")
+    );
+}
+
+#[test]
+fn derives_eui64_identifiers_from_mac_addresses() {
+    let mac = "00:11:22:33:44:55".parse::<InterfaceId>().unwrap();
+
+    assert_eq!(
+        mac,
+        InterfaceId::Eui64([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+    );
+    assert_eq!("00-11-22-33-44-55".parse::<InterfaceId>().unwrap(), mac);
+    assert_eq!(
+        mac.apply(ipv6("2001:db8:1:2::1"), 64),
+        ipv6("2001:db8:1:2:211:22ff:fe33:4455")
+    );
+    assert!("00:11:22:33:44".parse::<InterfaceId>().is_err());
+}
+
+#[test]
+fn parses_domains_with_interface_identifiers() {
+    let domain = "nas.example.com:AAAA=::10".parse::<DomainArg>().unwrap();
+
+    assert_eq!(domain.name, "nas.example.com");
+    assert_eq!(domain.interface_id, Some(InterfaceId::Suffix(ipv6("::10"))));
+    assert_eq!(domain.families(), [IpFamily::V6]);
+
+    let domain = "pc.example.com=00:11:22:33:44:55"
+        .parse::<DomainArg>()
+        .unwrap();
+    assert_eq!(
+        domain.interface_id,
+        Some(InterfaceId::Eui64([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]))
+    );
+    assert_eq!(domain.families(), [IpFamily::V6]);
+    assert!("nas.example.com:A=::10".parse::<DomainArg>().is_err());
+    assert!("nas.example.com=not-an-id".parse::<DomainArg>().is_err());
+}
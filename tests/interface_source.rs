@@ -0,0 +1,81 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::net::IpAddr;
+
+use common::netns::{add_interface, in_network_namespace, ip};
+use dns_updater::wan_ip_query::{IpFamily, IpSourceSpec};
+
+fn query(family: IpFamily) -> Result<IpAddr, String> {
+    let source = "interface:wan0".parse::<IpSourceSpec>().unwrap().create();
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(source.query(family))
+        .map_err(|err| err.to_string())
+}
+
+/// Sets an IPv6 setting of wan0. /proc/sys/net belongs to the network namespace of the thread.
+fn set_ipv6_conf(name: &str, value: &str) {
+    std::fs::write(format!("/proc/sys/net/ipv6/conf/wan0/{name}"), value)
+        .unwrap_or_else(|err| panic!("Failed to set {name}: {err}"));
+}
+
+/// Adds a wan0 interface that creates temporary (privacy extension) addresses right away.
+fn add_interface_with_temporary_addresses() {
+    add_interface();
+    set_ipv6_conf("accept_dad", "0");
+    set_ipv6_conf("use_tempaddr", "2");
+}
+
+#[test]
+fn picks_the_global_ipv6_address() {
+    in_network_namespace(|| {
+        add_interface_with_temporary_addresses();
+        ip("addr add fd00::7/64 dev wan0");
+        ip("addr add fe80::7/64 dev wan0");
+        ip("addr add 2001:db8:1::7/64 dev wan0 preferred_lft 0");
+        // Also creates a temporary address in the same prefix
+        ip("addr add 2001:db8::7/64 dev wan0 mngtmpaddr");
+
+        let output = std::process::Command::new("ip")
+            .args(["-6", "addr", "show", "dev", "wan0", "temporary"])
+            .output()
+            .unwrap();
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("2001:db8::"),
+            "The kernel should have created a temporary address"
+        );
+
+        assert_eq!(query(IpFamily::V6), Ok("2001:db8::7".parse().unwrap()));
+    });
+}
+
+#[test]
+fn picks_the_public_ipv4_address() {
+    in_network_namespace(|| {
+        add_interface();
+        ip("addr add 10.0.0.7/8 dev wan0");
+        ip("addr add 169.254.0.7/16 dev wan0");
+        ip("addr add 198.51.100.7/24 dev wan0");
+
+        assert_eq!(query(IpFamily::V4), Ok("198.51.100.7".parse().unwrap()));
+    });
+}
+
+#[test]
+fn fails_without_a_usable_ipv6_address() {
+    in_network_namespace(|| {
+        add_interface_with_temporary_addresses();
+        ip("addr add fd00::7/64 dev wan0");
+        ip("addr add 2001:db8:1::7/64 dev wan0 preferred_lft 0");
+
+        assert_eq!(
+            query(IpFamily::V6),
+            Err("Query failed: Interface wan0 has no public IPv6 address".to_string())
+        );
+    });
+}
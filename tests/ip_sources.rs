@@ -0,0 +1,818 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::{http::HeaderMap, routing::get, routing::post, Router};
+use dns_updater::{
+    address_policy::AddressPolicy,
+    wan_ip_query::{
+        query_wan_ip, query_wan_ip_quorum, CommandSpec, DnsLookup, Extract, HttpEndpoint, IpFamily,
+        IpSource, IpSourceSpec, WanIpError,
+    },
+};
+use hickory_proto::{
+    op::{Message, MessageType},
+    rr::{rdata, RData, Record, RecordType},
+};
+use tokio::net::{TcpListener, UdpSocket};
+use url::Url;
+
+/// The device description of a fake UPnP Internet Gateway Device, with the WAN service nested
+/// in sub devices like real routers do.
+const IGD_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+    </serviceList>
+    <deviceList>
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+        <deviceList>
+          <device>
+            <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+            <serviceList>
+              <service>
+                <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                <controlURL>/ctl/IPConn</controlURL>
+              </service>
+            </serviceList>
+          </device>
+        </deviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+/// Starts a fake UPnP gateway that reports `external_ip`, and returns its description url.
+async fn start_fake_igd(external_ip: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/rootDesc.xml", listener.local_addr().unwrap());
+
+    let app = Router::new()
+        .route("/rootDesc.xml", get(|| async { IGD_DESCRIPTION }))
+        .route(
+            "/ctl/IPConn",
+            post(move |headers: HeaderMap| async move {
+                assert_eq!(
+                    headers["SOAPAction"],
+                    "\"urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress\""
+                );
+
+                format!(
+                    r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+  <s:Body>
+    <u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+      <NewExternalIPAddress>{external_ip}</NewExternalIPAddress>
+    </u:GetExternalIPAddressResponse>
+  </s:Body>
+</s:Envelope>"#
+                )
+            }),
+        );
+
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    url
+}
+
+/// Starts a NAT-PMP and PCP server on localhost that reports `external_ip`, and returns its address.
+/// Every received request is sent to the returned channel.
+async fn start_fake_pcp_server(
+    external_ip: Ipv4Addr,
+) -> (String, tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 1100];
+
+        loop {
+            let (length, client) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = buffer[..length].to_vec();
+
+            let response = match request[..2] {
+                // NAT-PMP external address request
+                [0, 0] => {
+                    let mut response = vec![0, 128, 0, 0, 0, 0, 0, 1];
+                    response.extend(external_ip.octets());
+                    response
+                }
+                // PCP MAP request, answered with the same nonce, protocol and port
+                [2, 1] => {
+                    let mut response = vec![2, 0x81, 0, 0];
+                    response.extend(&request[4..8]);
+                    response.extend([0; 16]);
+                    response.extend(&request[24..44]);
+                    response.extend(external_ip.to_ipv6_mapped().octets());
+                    response
+                }
+                _ => continue,
+            };
+
+            sender.send(request).unwrap();
+            socket.send_to(&response, client).await.unwrap();
+        }
+    });
+
+    (address, receiver)
+}
+
+/// Starts a STUN server on localhost that reports `mapped_ip` as the client's address,
+/// and returns its address.
+async fn start_fake_stun_server(bind_address: &str, mapped_ip: IpAddr) -> String {
+    let socket = UdpSocket::bind(bind_address).await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 1100];
+
+        loop {
+            let (length, client) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = &buffer[..length];
+
+            if length < 20 || request[..2] != [0x00, 0x01] {
+                continue;
+            }
+
+            let (family, octets) = match mapped_ip {
+                IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+                IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+            };
+            // The magic cookie followed by the transaction id
+            let mask = &request[4..20];
+            let port = client.port() ^ 0x2112;
+
+            let mut attributes = vec![];
+            // SOFTWARE, with a length that needs padding
+            attributes.extend([0x80, 0x22, 0x00, 0x05]);
+            attributes.extend(b"fake\0\0\0\0");
+            // XOR-MAPPED-ADDRESS
+            attributes.extend([0x00, 0x20, 0x00, 4 + octets.len() as u8, 0, family]);
+            attributes.extend(port.to_be_bytes());
+            attributes.extend(octets.iter().zip(mask).map(|(byte, mask)| byte ^ mask));
+
+            let mut response = vec![0x01, 0x01];
+            response.extend((attributes.len() as u16).to_be_bytes());
+            response.extend(mask);
+            response.extend(attributes);
+
+            socket.send_to(&response, client).await.unwrap();
+        }
+    });
+
+    address
+}
+
+/// Starts a DNS server on localhost that answers every A, AAAA and TXT query with `ip`,
+/// and returns its address.
+async fn start_fake_dns_server(ip: IpAddr) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+
+        loop {
+            let (length, client) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_vec(&buffer[..length]).unwrap();
+            let query = request.queries()[0].clone();
+
+            let answers = match (query.query_type(), ip) {
+                (RecordType::A, IpAddr::V4(ip)) => vec![RData::A(rdata::A(ip))],
+                (RecordType::AAAA, IpAddr::V6(ip)) => vec![RData::AAAA(rdata::AAAA(ip))],
+                // Like Google's servers, with the client subnet before the address
+                (RecordType::TXT, ip) => vec![
+                    RData::TXT(rdata::TXT::new(vec![
+                        "edns0-client-subnet 192.0.2.0/24".to_string()
+                    ])),
+                    RData::TXT(rdata::TXT::new(vec![ip.to_string()])),
+                ],
+                _ => vec![],
+            };
+
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .add_query(query.clone());
+
+            for answer in answers {
+                response.add_answer(Record::from_rdata(query.name().clone(), 60, answer));
+            }
+
+            socket
+                .send_to(&response.to_vec().unwrap(), client)
+                .await
+                .unwrap();
+        }
+    });
+
+    address
+}
+
+/// A source that always returns the same IP, or fails if it has none.
+struct FixedSource(&'static str, Option<&'static str>);
+
+#[async_trait]
+impl IpSource for FixedSource {
+    fn name(&self) -> String {
+        self.0.to_string()
+    }
+
+    async fn query(&self, _family: IpFamily) -> Result<IpAddr, WanIpError> {
+        match self.1 {
+            Some(ip) => Ok(ip.parse().unwrap()),
+            None => Err(WanIpError::QueryFailed(anyhow::anyhow!("Unreachable"))),
+        }
+    }
+}
+
+fn fixed_sources(sources: &[(&'static str, Option<&'static str>)]) -> Vec<Box<dyn IpSource>> {
+    sources
+        .iter()
+        .map(|(name, ip)| Box::new(FixedSource(name, *ip)) as Box<dyn IpSource>)
+        .collect()
+}
+
+/// The default policy, but with the documentation ranges that the tests use allowed.
+fn documentation_allowed() -> AddressPolicy {
+    AddressPolicy {
+        allowed: vec![
+            "198.51.100.0/24".parse().unwrap(),
+            "203.0.113.0/24".parse().unwrap(),
+        ],
+        ..AddressPolicy::default()
+    }
+}
+
+/// Starts an HTTP server that answers `/<index>` with the IP at that index, and returns
+/// the url of each IP.
+async fn start_echo_server(ips: &[&'static str]) -> Vec<Url> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let mut app = Router::new();
+
+    for (index, &ip) in ips.iter().enumerate() {
+        app = app.route(&format!("/{index}"), get(move || async move { ip }));
+    }
+
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (0..ips.len())
+        .map(|index| Url::parse(&format!("{url}/{index}")).unwrap())
+        .collect()
+}
+
+/// Starts an HTTP server with IP echo endpoints in several formats, and returns its url.
+async fn start_structured_echo_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let app = Router::new()
+        .route(
+            "/json",
+            get(|| async {
+                axum::Json(serde_json::json!({ "ip": "198.51.100.7", "country": "NL" }))
+            }),
+        )
+        .route(
+            "/html",
+            get(|| async { "<html><body>Current IP Address: 198.51.100.8</body></html>" }),
+        )
+        .route(
+            "/header",
+            get(|| async { ([("X-Client-Ip", "198.51.100.9")], "ok") }),
+        )
+        .route(
+            "/private",
+            get(|headers: HeaderMap| async move {
+                if headers
+                    .get("Authorization")
+                    .is_some_and(|value| value == "Bearer secret")
+                {
+                    Ok("198.51.100.10\n")
+                } else {
+                    Err(axum::http::StatusCode::UNAUTHORIZED)
+                }
+            }),
+        )
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                "198.51.100.11"
+            }),
+        );
+
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    url
+}
+
+async fn query_endpoint(endpoint: &str) -> Result<IpAddr, WanIpError> {
+    format!("http:{endpoint}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create()
+        .query(IpFamily::V4)
+        .await
+}
+
+#[test]
+fn parses_http_endpoints() {
+    let endpoint =
+        r#"https://ipinfo.io/json json=/ip header="Authorization: Bearer abc" timeout=2.5"#
+            .parse::<HttpEndpoint>()
+            .unwrap();
+
+    assert_eq!(endpoint.url.as_str(), "https://ipinfo.io/json");
+    assert_eq!(endpoint.extract, Extract::JsonPointer("/ip".to_string()));
+    assert_eq!(
+        endpoint.headers,
+        [("Authorization".to_string(), "Bearer abc".to_string())]
+    );
+    assert_eq!(
+        endpoint.timeout,
+        Some(std::time::Duration::from_millis(2500))
+    );
+
+    assert!("https://ipinfo.io/json json=ip"
+        .parse::<HttpEndpoint>()
+        .is_err());
+    assert!("https://ipinfo.io regex=(".parse::<HttpEndpoint>().is_err());
+    assert!("https://ipinfo.io json=/ip regex=ip"
+        .parse::<HttpEndpoint>()
+        .is_err());
+    assert!("https://ipinfo.io format=xml"
+        .parse::<HttpEndpoint>()
+        .is_err());
+}
+
+#[test]
+fn parses_ip_source_specs() {
+    assert_eq!(
+        "http".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Http(None)
+    );
+    assert_eq!(
+        "interface:eth0".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Interface("eth0".to_string())
+    );
+    assert!("interface".parse::<IpSourceSpec>().is_err());
+    assert_eq!(
+        "upnp".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Upnp(None)
+    );
+    assert_eq!(
+        "natpmp:192.168.1.1".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::NatPmp(Some("192.168.1.1:5351".parse().unwrap()))
+    );
+    assert_eq!(
+        "pcp:[fe80::1]:5351".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Pcp(Some("[fe80::1]:5351".parse().unwrap()))
+    );
+    assert!("pcp:gateway".parse::<IpSourceSpec>().is_err());
+    assert_eq!(
+        "stun:stun.example.com".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Stun(Some(vec!["stun.example.com:3478".to_string()]))
+    );
+    assert_eq!(
+        "stun:2001:db8::1".parse::<IpSourceSpec>().unwrap(),
+        IpSourceSpec::Stun(Some(vec!["[2001:db8::1]:3478".to_string()]))
+    );
+    assert_eq!(
+        "dns:txt:o-o.myaddr.l.google.com@ns1.google.com"
+            .parse::<IpSourceSpec>()
+            .unwrap(),
+        IpSourceSpec::Dns(Some(vec![DnsLookup {
+            name: "o-o.myaddr.l.google.com".to_string(),
+            server: "ns1.google.com:53".to_string(),
+            txt: true,
+        }]))
+    );
+    assert!("dns:myip.opendns.com".parse::<IpSourceSpec>().is_err());
+    assert_eq!(
+        "command:ssh router show ip"
+            .parse::<IpSourceSpec>()
+            .unwrap(),
+        IpSourceSpec::Command("ssh router show ip".to_string().into())
+    );
+    assert_eq!(
+        "command:timeout=2.5 ssh router show ip"
+            .parse::<IpSourceSpec>()
+            .unwrap(),
+        IpSourceSpec::Command(CommandSpec {
+            command: "ssh router show ip".to_string(),
+            timeout: Duration::from_millis(2500),
+        })
+    );
+    assert!("command:".parse::<IpSourceSpec>().is_err());
+    assert!("command:timeout=5".parse::<IpSourceSpec>().is_err());
+    assert!("command:timeout=0 ssh router show ip"
+        .parse::<IpSourceSpec>()
+        .is_err());
+    assert!("carrier-pigeon".parse::<IpSourceSpec>().is_err());
+}
+
+#[tokio::test]
+async fn interface_source_skips_non_public_addresses() {
+    let source = "interface:lo".parse::<IpSourceSpec>().unwrap().create();
+
+    // The loopback interface only has loopback addresses
+    assert!(source.query(IpFamily::V4).await.is_err());
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn interface_source_fails_for_unknown_interfaces() {
+    let source = "interface:does-not-exist0"
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert!(source.query(IpFamily::V4).await.is_err());
+}
+
+#[tokio::test]
+async fn upnp_source_asks_the_gateway() {
+    let description_url = start_fake_igd("203.0.113.7").await;
+    let source = format!("upnp:{description_url}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "203.0.113.7".parse::<IpAddr>().unwrap()
+    );
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn upnp_source_fails_when_the_gateway_is_disconnected() {
+    let description_url = start_fake_igd("0.0.0.0").await;
+    let source = format!("upnp:{description_url}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert!(source.query(IpFamily::V4).await.is_err());
+}
+
+#[tokio::test]
+async fn natpmp_source_asks_the_gateway() {
+    let (address, _requests) = start_fake_pcp_server(Ipv4Addr::new(203, 0, 113, 8)).await;
+    let source = format!("natpmp:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "203.0.113.8".parse::<IpAddr>().unwrap()
+    );
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn natpmp_source_fails_when_the_gateway_is_disconnected() {
+    let (address, _requests) = start_fake_pcp_server(Ipv4Addr::UNSPECIFIED).await;
+    let source = format!("natpmp:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert!(source.query(IpFamily::V4).await.is_err());
+}
+
+#[tokio::test]
+async fn pcp_source_maps_and_deletes_a_port() {
+    let (address, mut requests) = start_fake_pcp_server(Ipv4Addr::new(203, 0, 113, 9)).await;
+    let source = format!("pcp:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "203.0.113.9".parse::<IpAddr>().unwrap()
+    );
+
+    let map = requests.recv().await.unwrap();
+    let delete = requests.recv().await.unwrap();
+
+    // Requested lifetime, client address and suggested external address
+    assert_ne!(map[4..8], [0; 4]);
+    assert_eq!(map[8..24], Ipv4Addr::LOCALHOST.to_ipv6_mapped().octets());
+    assert_eq!(map[44..60], Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+    assert_eq!(delete[4..8], [0; 4]);
+    assert_eq!(delete[24..44], map[24..44]);
+}
+
+#[tokio::test]
+async fn pcp_source_fails_without_an_address_of_the_family() {
+    let (address, _requests) = start_fake_pcp_server(Ipv4Addr::new(203, 0, 113, 9)).await;
+    let source = format!("pcp:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    // The server only has an IPv4 address to give
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn stun_source_reads_the_mapped_address() {
+    let address = start_fake_stun_server("127.0.0.1:0", "198.51.100.4".parse().unwrap()).await;
+    let source = format!("stun:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "198.51.100.4".parse::<IpAddr>().unwrap()
+    );
+    // The server has no IPv6 address
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn stun_source_reads_mapped_ipv6_addresses() {
+    let address = start_fake_stun_server("[::1]:0", "2001:db8::4".parse().unwrap()).await;
+    let source = format!("stun:{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V6).await.unwrap(),
+        "2001:db8::4".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn dns_source_reads_address_records() {
+    let address = start_fake_dns_server("198.51.100.5".parse().unwrap()).await;
+    let source = format!("dns:myip.example.com@{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "198.51.100.5".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn dns_source_reads_txt_records() {
+    let address = start_fake_dns_server("198.51.100.6".parse().unwrap()).await;
+    let source = format!("dns:txt:o-o.myaddr.example.com@{address}")
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "198.51.100.6".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn quorum_accepts_an_ip_that_enough_sources_agree_on() {
+    let sources = fixed_sources(&[
+        ("a", Some("198.51.100.1")),
+        ("b", Some("203.0.113.66")),
+        ("c", Some("198.51.100.1")),
+        ("d", None),
+    ]);
+
+    let (ip, description) =
+        query_wan_ip_quorum(&sources, IpFamily::V4, 2, &documentation_allowed())
+            .await
+            .unwrap();
+
+    assert_eq!(ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+    assert_eq!(description, "2/4 sources: a, c");
+}
+
+#[tokio::test]
+async fn quorum_rejects_ips_without_enough_agreement() {
+    let sources = fixed_sources(&[
+        ("a", Some("198.51.100.1")),
+        ("b", Some("203.0.113.66")),
+        ("c", None),
+    ]);
+
+    let result = query_wan_ip_quorum(&sources, IpFamily::V4, 2, &documentation_allowed()).await;
+
+    assert!(matches!(result, Err(WanIpError::NoQuorum(2, _))));
+}
+
+#[tokio::test]
+async fn quorum_rejects_conflicting_ips() {
+    let sources = fixed_sources(&[("a", Some("198.51.100.1")), ("b", Some("203.0.113.66"))]);
+
+    // Both IPs reach the quorum, so neither can be trusted
+    let result = query_wan_ip_quorum(&sources, IpFamily::V4, 1, &documentation_allowed()).await;
+
+    assert!(matches!(result, Err(WanIpError::NoQuorum(1, _))));
+}
+
+#[tokio::test]
+async fn quorum_counts_each_http_endpoint() {
+    let urls = start_echo_server(&["198.51.100.1", "198.51.100.1", "203.0.113.66"]).await;
+    let endpoints = urls.into_iter().map(HttpEndpoint::from).collect();
+    let sources = vec![IpSourceSpec::Http(Some(endpoints)).create()];
+
+    let (ip, _) = query_wan_ip_quorum(&sources, IpFamily::V4, 2, &documentation_allowed())
+        .await
+        .unwrap();
+
+    assert_eq!(ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+    assert!(
+        query_wan_ip_quorum(&sources, IpFamily::V4, 3, &documentation_allowed())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn quorum_counts_duplicate_endpoints_once() {
+    let sources = fixed_sources(&[("a", Some("198.51.100.1")), ("a", Some("198.51.100.1"))]);
+
+    assert!(
+        query_wan_ip_quorum(&sources, IpFamily::V4, 2, &documentation_allowed())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn sources_that_return_reserved_addresses_are_skipped() {
+    let sources = fixed_sources(&[("a", Some("192.168.1.1")), ("b", Some("5.6.7.8"))]);
+
+    let (ip, name) = query_wan_ip(&sources, IpFamily::V4, &AddressPolicy::default())
+        .await
+        .unwrap();
+
+    assert_eq!(ip, "5.6.7.8".parse::<IpAddr>().unwrap());
+    assert_eq!(name, "b");
+
+    let result = query_wan_ip(&sources[..1], IpFamily::V4, &AddressPolicy::default()).await;
+
+    match result {
+        Err(WanIpError::Rejected(name, rejected)) => {
+            assert_eq!(name, "a");
+            assert_eq!(rejected.reason, "a private address");
+            assert_eq!(rejected.range, "192.168.0.0/16".parse().unwrap());
+        }
+        result => panic!("Expected the address to be rejected, got {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn quorum_ignores_reserved_addresses() {
+    let sources = fixed_sources(&[
+        ("a", Some("10.0.0.1")),
+        ("b", Some("10.0.0.1")),
+        ("c", Some("5.6.7.8")),
+    ]);
+
+    let result = query_wan_ip_quorum(&sources, IpFamily::V4, 2, &AddressPolicy::default()).await;
+
+    assert!(matches!(result, Err(WanIpError::NoQuorum(2, _))));
+}
+
+#[tokio::test]
+async fn http_source_extracts_ips_from_responses() {
+    let url = start_structured_echo_server().await;
+
+    assert_eq!(
+        query_endpoint(&format!("{url}/json json=/ip"))
+            .await
+            .unwrap(),
+        "198.51.100.7".parse::<IpAddr>().unwrap()
+    );
+    assert_eq!(
+        query_endpoint(&format!(r"{url}/html regex=Address:\s*([0-9.]+)"))
+            .await
+            .unwrap(),
+        "198.51.100.8".parse::<IpAddr>().unwrap()
+    );
+    assert_eq!(
+        query_endpoint(&format!("{url}/header response-header=x-client-ip"))
+            .await
+            .unwrap(),
+        "198.51.100.9".parse::<IpAddr>().unwrap()
+    );
+    assert!(query_endpoint(&format!("{url}/json json=/country"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn http_source_sends_request_headers() {
+    let url = start_structured_echo_server().await;
+
+    assert_eq!(
+        query_endpoint(&format!(
+            r#"{url}/private header="Authorization: Bearer secret""#
+        ))
+        .await
+        .unwrap(),
+        "198.51.100.10".parse::<IpAddr>().unwrap()
+    );
+    assert!(query_endpoint(&format!("{url}/private")).await.is_err());
+}
+
+#[tokio::test]
+async fn http_source_times_out() {
+    let url = start_structured_echo_server().await;
+    let started = std::time::Instant::now();
+
+    assert!(query_endpoint(&format!("{url}/slow timeout=0.2"))
+        .await
+        .is_err());
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn command_source_reads_the_output() {
+    let source = "command:printf '2001:db8::5\\n198.51.100.20\\n'"
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "198.51.100.20".parse::<IpAddr>().unwrap()
+    );
+    assert_eq!(
+        source.query(IpFamily::V6).await.unwrap(),
+        "2001:db8::5".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn command_source_tells_the_command_the_family() {
+    let source = r#"command:[ "$DNS_UPDATER_IP_FAMILY" = 4 ] && echo 198.51.100.21"#
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert_eq!(
+        source.query(IpFamily::V4).await.unwrap(),
+        "198.51.100.21".parse::<IpAddr>().unwrap()
+    );
+    assert!(source.query(IpFamily::V6).await.is_err());
+}
+
+#[tokio::test]
+async fn command_source_fails_on_errors() {
+    let source = "command:echo 198.51.100.22; exit 3"
+        .parse::<IpSourceSpec>()
+        .unwrap()
+        .create();
+
+    assert!(source.query(IpFamily::V4).await.is_err());
+}
+
+#[tokio::test]
+async fn command_source_times_out() {
+    let marker = std::env::temp_dir().join(format!(
+        "dns-updater-test-{}-command-timeout",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&marker);
+    let source = format!(
+        "command:timeout=0.2 sleep 1; touch {}; echo 198.51.100.23",
+        marker.display()
+    )
+    .parse::<IpSourceSpec>()
+    .unwrap()
+    .create();
+    let started = std::time::Instant::now();
+
+    let err = source.query(IpFamily::V4).await.unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("The command didn't finish within 0.2 seconds"),
+        "Unexpected error: {err}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // The command was killed before it got to the rest of the script
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!marker.exists());
+}
@@ -0,0 +1,514 @@
+mod common;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use common::{TempFile, WAN_IP};
+use dns_updater::{
+    provider::{DnsProvider, DnsRecord, ProviderConfig, ProviderError, Zone},
+    rfc2136::{Rfc2136, Rfc2136Config, TsigAlgorithmArg, TsigKey},
+    updater,
+    wan_ip_query::IpSourceSpec,
+};
+use hickory_proto::{
+    dnssec::{
+        rdata::tsig::{make_tsig_record, TSIG},
+        tsig::TSigner,
+    },
+    op::{Message, MessageType, OpCode, ResponseCode, UpdateMessage},
+    rr::{DNSClass, Name, RData, Record, RecordType},
+    serialize::binary::BinEncoder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+};
+
+const KEY_NAME: &str = "ddns-key";
+const SECRET: &[u8] = b"the shared secret of the test key";
+const FUDGE: u16 = 300;
+/// The TSIG error of a request with a wrong MAC.
+const BADSIG: u16 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+struct FakeState {
+    /// The records of the example.net zone.
+    records: Vec<Record>,
+    signer: Option<TSigner>,
+    /// Answer requests over UDP with an empty truncated response.
+    truncate_udp: bool,
+    /// Answer requests with this code instead of handling them.
+    response_code: Option<ResponseCode>,
+    requests: Vec<(Transport, Message)>,
+}
+
+/// A fake primary server for example.net that applies updates to an in-memory zone.
+/// If it has a key, requests are only handled if they're signed with it, and the responses
+/// are signed too.
+struct FakeServer {
+    address: SocketAddr,
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeServer {
+    async fn start(algorithm: Option<TsigAlgorithmArg>) -> Self {
+        // The client sends UDP and TCP requests to the same port
+        let (udp, tcp) = loop {
+            let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()).await {
+                break (udp, tcp);
+            }
+        };
+
+        let address = udp.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(FakeState {
+            records: vec![],
+            signer: algorithm.map(|algorithm| {
+                TSigner::new(SECRET.to_vec(), algorithm.into(), name(KEY_NAME), FUDGE).unwrap()
+            }),
+            truncate_udp: false,
+            response_code: None,
+            requests: vec![],
+        }));
+
+        let udp_state = state.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 4096];
+
+            loop {
+                let (length, peer) = udp.recv_from(&mut buffer).await.unwrap();
+                let response = handle(&udp_state, Transport::Udp, &buffer[..length]);
+                udp.send_to(&response, peer).await.unwrap();
+            }
+        });
+
+        let tcp_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = tcp.accept().await.unwrap();
+                let length = stream.read_u16().await.unwrap();
+                let mut request = vec![0; length as usize];
+                stream.read_exact(&mut request).await.unwrap();
+
+                let response = handle(&tcp_state, Transport::Tcp, &request);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+
+        FakeServer { address, state }
+    }
+
+    fn add_record(&self, fqdn: &str, ip: &str) {
+        let rdata = match ip.parse::<IpAddr>().unwrap() {
+            IpAddr::V4(ip) => RData::A(ip.into()),
+            IpAddr::V6(ip) => RData::AAAA(ip.into()),
+        };
+
+        self.state
+            .lock()
+            .unwrap()
+            .records
+            .push(Record::from_rdata(name(fqdn), 1800, rdata));
+    }
+
+    /// The values and TTLs of the `ty` records at `fqdn`.
+    fn records(&self, fqdn: &str, ty: RecordType) -> Vec<(String, u32)> {
+        self.state
+            .lock()
+            .unwrap()
+            .records
+            .iter()
+            .filter(|record| *record.name() == name(fqdn) && record.record_type() == ty)
+            .map(|record| (record.data().ip_addr().unwrap().to_string(), record.ttl()))
+            .collect()
+    }
+
+    /// The update requests that were received.
+    fn updates(&self) -> Vec<(Transport, Message)> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|(_, request)| request.op_code() == OpCode::Update)
+            .cloned()
+            .collect()
+    }
+
+    fn config(&self, algorithm: Option<TsigAlgorithmArg>) -> Rfc2136Config {
+        Rfc2136Config {
+            server: self.address.to_string(),
+            zones: vec!["example.net".to_string()],
+            tcp: false,
+            tsig_key: algorithm.map(|algorithm| TsigKey {
+                name: KEY_NAME.to_string(),
+                algorithm,
+                secret: SECRET.to_vec(),
+            }),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+fn name(name: &str) -> Name {
+    let mut name = Name::from_ascii(name).unwrap();
+    name.set_fqdn(true);
+
+    name
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn handle(state: &Mutex<FakeState>, transport: Transport, request: &[u8]) -> Vec<u8> {
+    let mut state = state.lock().unwrap();
+    let message = Message::from_vec(request).unwrap();
+    state.requests.push((transport, message.clone()));
+
+    let mut response = Message::new();
+    response
+        .set_id(message.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(message.op_code())
+        .add_queries(message.queries().to_vec());
+
+    let request_mac = match &state.signer {
+        Some(signer) => match signer.verify_message_byte(None, request, true) {
+            Ok((mac, _, _)) => Some(mac),
+            Err(_) => {
+                // The error response to a bad signature isn't signed
+                let tsig = TSIG::new(
+                    signer.algorithm().clone(),
+                    unix_time(),
+                    FUDGE,
+                    vec![],
+                    message.id(),
+                    BADSIG,
+                    vec![],
+                );
+                response
+                    .set_response_code(ResponseCode::NotAuth)
+                    .add_tsig(make_tsig_record(signer.signer_name().clone(), tsig));
+
+                return response.to_vec().unwrap();
+            }
+        },
+        None => None,
+    };
+
+    if transport == Transport::Udp && state.truncate_udp {
+        response.set_truncated(true);
+    } else if let Some(code) = state.response_code {
+        response.set_response_code(code);
+    } else if message.op_code() == OpCode::Update {
+        let code = apply_update(&mut state.records, &message);
+        response.set_response_code(code);
+    } else {
+        let query = &message.queries()[0];
+        let answers = state
+            .records
+            .iter()
+            .filter(|record| {
+                record.name() == query.name() && record.record_type() == query.query_type()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        response.add_answers(answers);
+    }
+
+    if let (Some(signer), Some(request_mac)) = (&state.signer, request_mac) {
+        let tsig = TSIG::new(
+            signer.algorithm().clone(),
+            unix_time(),
+            FUDGE,
+            vec![],
+            response.id(),
+            0,
+            vec![],
+        );
+        // The MAC covers the request MAC and the response as it's sent. hickory's message_tbs
+        // encodes the response after the request MAC, which shifts its compression pointers
+        let mut tbs = (request_mac.len() as u16).to_be_bytes().to_vec();
+        tbs.extend(request_mac);
+        tbs.extend(response.to_vec().unwrap());
+        let mut variables = vec![];
+        tsig.emit_tsig_for_mac(&mut BinEncoder::new(&mut variables), signer.signer_name())
+            .unwrap();
+        tbs.extend(variables);
+        let mac = signer.sign(&tbs).unwrap();
+        response.add_tsig(make_tsig_record(
+            signer.signer_name().clone(),
+            tsig.set_mac(mac),
+        ));
+    }
+
+    response.to_vec().unwrap()
+}
+
+/// Applies the updates of a message for the example.net zone to `records`.
+fn apply_update(records: &mut Vec<Record>, message: &Message) -> ResponseCode {
+    let zone = &message.zones()[0];
+
+    if *zone.name() != name("example.net") || zone.query_type() != RecordType::SOA {
+        return ResponseCode::NotAuth;
+    }
+
+    for update in message.updates() {
+        match update.dns_class() {
+            // Deletes the RRset
+            DNSClass::ANY => records.retain(|record| {
+                record.name() != update.name() || record.record_type() != update.record_type()
+            }),
+            DNSClass::IN => records.push(update.clone()),
+            _ => return ResponseCode::FormErr,
+        }
+    }
+
+    ResponseCode::NoError
+}
+
+fn zone() -> Zone {
+    Zone {
+        id: "example.net".to_string(),
+        name: "example.net".to_string(),
+    }
+}
+
+fn home_record() -> DnsRecord {
+    DnsRecord {
+        id: "home.example.net/A".to_string(),
+        ty: "A".to_string(),
+        name: "home".to_string(),
+        data: "1.2.3.4".to_string(),
+        ttl: 1800,
+        proxied: None,
+    }
+}
+
+#[tokio::test]
+async fn replaces_the_rrset_with_a_signed_update() {
+    let server = FakeServer::start(Some(TsigAlgorithmArg::HmacSha256)).await;
+    server.add_record("home.example.net", "1.2.3.4");
+    server.add_record("home.example.net", "1.2.3.5");
+    server.add_record("home.example.net", "2001:db8::1");
+    let state_file = TempFile::new("replaces_the_rrset_with_a_signed_update");
+
+    let mut settings = common::settings(
+        ProviderConfig::Rfc2136(server.config(Some(TsigAlgorithmArg::HmacSha256))),
+        "http://127.0.0.1:1",
+        &["home.example.net:A"],
+        true,
+        &state_file,
+    );
+    settings.entries[0].ip_sources = vec![IpSourceSpec::Command(format!("echo {WAN_IP}").into())];
+    settings.entries[0].ttl.ttl = Some(300);
+    updater::start(settings).await.unwrap();
+
+    assert_eq!(
+        server.records("home.example.net", RecordType::A),
+        [(WAN_IP.to_string(), 300)]
+    );
+    assert_eq!(
+        server.records("home.example.net", RecordType::AAAA),
+        [("2001:db8::1".to_string(), 1800)]
+    );
+
+    let updates = server.updates();
+    assert_eq!(updates.len(), 1);
+
+    let (transport, update) = &updates[0];
+    assert_eq!(*transport, Transport::Udp);
+    assert_eq!(update.signature().len(), 1);
+    assert_eq!(update.zones().len(), 1);
+    assert_eq!(*update.zones()[0].name(), name("example.net"));
+    assert_eq!(update.zones()[0].query_type(), RecordType::SOA);
+    assert!(update.prerequisites().is_empty());
+
+    let [delete, add] = update.updates() else {
+        panic!("Expected a delete and an add, got {:?}", update.updates());
+    };
+    assert_eq!(*delete.name(), name("home.example.net"));
+    assert_eq!(delete.dns_class(), DNSClass::ANY);
+    assert_eq!(delete.record_type(), RecordType::A);
+    assert_eq!(delete.ttl(), 0);
+    assert!(delete.data().is_update0());
+    assert_eq!(*add.name(), name("home.example.net"));
+    assert_eq!(add.dns_class(), DNSClass::IN);
+    assert_eq!(add.data().ip_addr(), Some(WAN_IP.parse().unwrap()));
+    assert_eq!(add.ttl(), 300);
+}
+
+#[tokio::test]
+async fn signs_updates_with_hmac_sha512() {
+    let server = FakeServer::start(Some(TsigAlgorithmArg::HmacSha512)).await;
+    server.add_record("home.example.net", "1.2.3.4");
+    let provider = Rfc2136::new(server.config(Some(TsigAlgorithmArg::HmacSha512))).unwrap();
+
+    let records = provider
+        .list_records(&zone(), &["home.example.net"])
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].data, "1.2.3.4");
+
+    provider
+        .update_record(&zone(), &records[0], WAN_IP, 60)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.records("home.example.net", RecordType::A),
+        [(WAN_IP.to_string(), 60)]
+    );
+}
+
+#[tokio::test]
+async fn deletes_the_rrset_of_a_record() {
+    let server = FakeServer::start(Some(TsigAlgorithmArg::HmacSha256)).await;
+    server.add_record("home.example.net", "1.2.3.4");
+    server.add_record("home.example.net", "1.2.3.5");
+    server.add_record("home.example.net", "2001:db8::1");
+    let provider = Rfc2136::new(server.config(Some(TsigAlgorithmArg::HmacSha256))).unwrap();
+
+    provider
+        .delete_record(&zone(), &home_record())
+        .await
+        .unwrap();
+
+    assert!(server.records("home.example.net", RecordType::A).is_empty());
+    assert_eq!(
+        server.records("home.example.net", RecordType::AAAA),
+        [("2001:db8::1".to_string(), 1800)]
+    );
+
+    let updates = server.updates();
+    let [delete] = updates[0].1.updates() else {
+        panic!("Expected a single delete, got {:?}", updates[0].1.updates());
+    };
+    assert_eq!(delete.dns_class(), DNSClass::ANY);
+    assert_eq!(delete.record_type(), RecordType::A);
+}
+
+#[tokio::test]
+async fn retries_truncated_responses_over_tcp() {
+    let server = FakeServer::start(Some(TsigAlgorithmArg::HmacSha256)).await;
+    server.add_record("home.example.net", "1.2.3.4");
+    server.state.lock().unwrap().truncate_udp = true;
+    let provider = Rfc2136::new(server.config(Some(TsigAlgorithmArg::HmacSha256))).unwrap();
+
+    provider
+        .update_record(&zone(), &home_record(), WAN_IP, 1800)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        server.records("home.example.net", RecordType::A),
+        [(WAN_IP.to_string(), 1800)]
+    );
+    assert_eq!(
+        server
+            .updates()
+            .iter()
+            .map(|(transport, _)| *transport)
+            .collect::<Vec<_>>(),
+        [Transport::Udp, Transport::Tcp]
+    );
+}
+
+#[tokio::test]
+async fn refused_and_notauth_responses_are_unauthorized() {
+    for code in [ResponseCode::Refused, ResponseCode::NotAuth] {
+        let server = FakeServer::start(None).await;
+        server.add_record("home.example.net", "1.2.3.4");
+        server.state.lock().unwrap().response_code = Some(code);
+        let provider = Rfc2136::new(server.config(None)).unwrap();
+
+        let result = provider
+            .update_record(&zone(), &home_record(), WAN_IP, 1800)
+            .await;
+
+        assert!(
+            matches!(result, Err(ProviderError::Unauthorized(_))),
+            "{code} should be unauthorized, got {result:?}"
+        );
+        assert_eq!(
+            server.records("home.example.net", RecordType::A),
+            [("1.2.3.4".to_string(), 1800)]
+        );
+    }
+}
+
+#[tokio::test]
+async fn wrong_tsig_key_is_unauthorized() {
+    let server = FakeServer::start(Some(TsigAlgorithmArg::HmacSha256)).await;
+    server.add_record("home.example.net", "1.2.3.4");
+
+    let mut config = server.config(Some(TsigAlgorithmArg::HmacSha256));
+    config.tsig_key.as_mut().unwrap().secret = b"another secret".to_vec();
+    let provider = Rfc2136::new(config).unwrap();
+
+    let result = provider
+        .update_record(&zone(), &home_record(), WAN_IP, 1800)
+        .await;
+
+    assert!(
+        matches!(result, Err(ProviderError::Unauthorized(_))),
+        "BADSIG should be unauthorized, got {result:?}"
+    );
+    assert_eq!(
+        server.records("home.example.net", RecordType::A),
+        [("1.2.3.4".to_string(), 1800)]
+    );
+}
+
+#[tokio::test]
+async fn other_error_responses_are_reported() {
+    let server = FakeServer::start(None).await;
+    server.state.lock().unwrap().response_code = Some(ResponseCode::ServFail);
+    let provider = Rfc2136::new(server.config(None)).unwrap();
+
+    let result = provider
+        .update_record(&zone(), &home_record(), WAN_IP, 1800)
+        .await;
+
+    assert!(
+        matches!(&result, Err(ProviderError::Other(err)) if err.to_string().contains("Server Failure")),
+        "Got {result:?}"
+    );
+}
+
+#[tokio::test]
+async fn invalid_values_and_unsupported_types_are_named() {
+    let server = FakeServer::start(None).await;
+    let provider = Rfc2136::new(server.config(None)).unwrap();
+
+    let err = provider
+        .update_record(&zone(), &home_record(), "2001:db8::1", 1800)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "2001:db8::1 is not a valid A value");
+
+    let err = provider
+        .create_record(&zone(), "home", "CNAME", "example.org.", 1800)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Record type CNAME is not supported by RFC 2136 updates"
+    );
+    assert!(server.updates().is_empty());
+}